@@ -1,19 +1,28 @@
 // yandex-ocr API client and Rig integration
 use reqwest::Client as HttpClient;
+use reqwest::header::{HeaderMap, HeaderName, HeaderValue};
 use rig::client::{CompletionClient, ProviderClient, VerifyClient, VerifyError};
 use rig::completion::{self, CompletionError, CompletionRequest, GetTokenUsage};
 use rig::message::{AssistantContent, DocumentSourceKind, MimeType};
-use rig::{OneOrMany, impl_conversion_traits, message};
+use rig::streaming::{RawStreamingChoice, StreamingCompletionResponse};
+use rig::{OneOrMany, http_client, impl_conversion_traits, message};
 use serde::{Deserialize, Serialize};
+#[cfg(feature = "yandex-yc-cli")]
 use std::process::Command;
 
-use std::error::Error;
-use std::fmt::{Debug, Display, Formatter, Result as FmtResult};
+use std::path::Path;
+use std::fmt::Debug;
+use std::sync::Arc;
+use tokio::sync::{Mutex, Semaphore};
 
+use async_stream::stream;
+use futures::StreamExt;
+use base64::{Engine, prelude::BASE64_STANDARD};
 use chrono::{Local, NaiveDateTime, TimeDelta};
 use regex::Regex;
+use tracing::{Instrument, info_span};
 use crate::providers::yandex::schemas::*;
-use std::{thread, time};
+use std::time;
 
 // ================================================================
 // Main Yandex Client
@@ -22,46 +31,514 @@ use std::{thread, time};
 // for temporary token life tracing - we expecting to reissue every
 // three hours
 const YA_OCR_TOKEN_UPD: TimeDelta = TimeDelta::try_hours(3).unwrap();
-const YA_BASE_URL: &'static str = "https://ocr.api.cloud.yandex.net/ocr/v1";
+// tokens with a known expiry are reissued this long before it
+const YA_TOKEN_REFRESH_MARGIN: TimeDelta = TimeDelta::try_minutes(5).unwrap();
+const YA_BASE_URL: &str = "https://ocr.api.cloud.yandex.net/ocr/v1";
+const YA_IAM_URL: &str = "https://iam.api.cloud.yandex.net/iam/v1/tokens";
+const YA_KZ_BASE_URL: &str = "https://ocr.api.yandexcloud.kz/ocr/v1";
+const YA_KZ_IAM_URL: &str = "https://iam.api.yandexcloud.kz/iam/v1/tokens";
+// token of the service account attached to a Yandex Compute instance
+const YA_METADATA_URL: &str =
+    "http://169.254.169.254/computeMetadata/v1/instance/service-accounts/default/token";
+
+// default polling of async recognition results: 30 attempts every 600ms,
+// i.e. roughly 18 seconds before giving up
+const YA_POLL_MAX_ATTEMPTS: usize = 30;
+const YA_POLL_INTERVAL: time::Duration = time::Duration::from_millis(600);
+
+// how long a single request to the OCR API may take
+const YA_REQUEST_TIMEOUT: time::Duration = time::Duration::from_secs(60);
+
+// retries of a recognition request answered with 429 or 5xx; the delay
+// doubles on every retry unless the server sends Retry-After
+const YA_MAX_RETRIES: usize = 3;
+const YA_RETRY_BACKOFF: time::Duration = time::Duration::from_millis(500);
+
+// longest wait between two attempts, whatever the backoff or the server's
+// Retry-After asks for
+const YA_MAX_RETRY_DELAY: time::Duration = YA_REQUEST_TIMEOUT;
+
+// how long downloading a document given by URL may take
+const YA_FETCH_TIMEOUT: time::Duration = time::Duration::from_secs(30);
+
+// maximum decoded content size accepted by the synchronous recognizeText
+// endpoint; bigger payloads are sent through recognizeTextAsync instead
+const YA_SYNC_MAX_CONTENT_BYTES: usize = 10 * 1024 * 1024;
+
+// maximum decoded content size Yandex accepts at all (recognizeTextAsync);
+// bigger documents are rejected before uploading them
+const YA_MAX_CONTENT_BYTES: usize = 100 * 1024 * 1024;
+
+// User-Agent of the HTTP clients built by the crate
+const YA_USER_AGENT: &str = concat!("rig-yandex-ocr/", env!("CARGO_PKG_VERSION"));
+
+// paths of the sync and async recognition requests and of the operation
+// polls
+const YA_SYNC_PATH: &str = "/recognizeText";
+const YA_ASYNC_PATH: &str = "/recognizeTextAsync";
+const YA_POLL_PATH: &str = "/getRecognition";
+
+// recognition operations a single completion runs at once
+const YA_MAX_CONCURRENT: usize = 4;
+
+// keys of the recognition request set by the crate, taking precedence over
+// the request additional params
+const YA_REQUEST_KEYS: [&str; 8] = [
+    "mimeType",
+    "languageCodes",
+    "model",
+    "content",
+    "markdown",
+    "tables",
+    "entities",
+    "dpi",
+];
+
+// language code asking Yandex to detect the languages of the document
+const YA_AUTO_LANGUAGE: &str = "*";
+
+// MIME types accepted by the OCR API
+const YA_SUPPORTED_MIME: [&str; 3] = ["image/jpeg", "image/png", "application/pdf"];
 
 // Current temporary token pattern used by Yandex. Subject to change in
 // https://yandex.cloud/ru/docs/iam/concepts/authorization/iam-token
-const YA_TOKEN_PATTERN: &'static str = "t1\\.[A-Z0-9a-z_-]+[=]{0,2}\\.[A-Z0-9a-z_-]{86}[=]{0,2}";
+const YA_TOKEN_PATTERN: &str = "t1\\.[A-Z0-9a-z_-]+[=]{0,2}\\.[A-Z0-9a-z_-]{86}[=]{0,2}";
 
 // -------------------------------------------------//
 // Miscalennious                                    //
 // -------------------------------------------------//
 #[derive(PartialEq, Clone, Debug)]
 pub enum AuthType {
+    /// IAM token issued by the `yc` CLI, see `YcCliTokenSource`
     Token,
+    /// IAM token exchanged natively for an OAuth token
+    OAuth,
+    /// IAM token exchanged natively for a JWT signed with a service
+    /// account key
+    ServiceAccount,
+    /// IAM token issued by a user supplied `TokenSource`
+    Provider,
+    /// IAM token of the instance service account, read from the Yandex
+    /// Compute metadata service
+    Metadata,
     ApiKey,
+    /// No credentials are sent, e.g. for mock servers or an authenticating
+    /// proxy; has to be requested explicitly with `ClientBuilder::no_auth`
     None,
 }
 
-#[derive(Debug)]
+/// Issues IAM tokens, e.g. through a different CLI, an HTTP call or from the
+/// runtime of a serverless function where the `yc` CLI is not available.
+/// Closures returning `Result<String, YaErr>` implement it as well.
+pub trait TokenSource: Send + Sync {
+    /// Issues a new IAM token. It is called on the blocking thread pool, so
+    /// it may block, e.g. on a subprocess.
+    fn issue_token(&self) -> Result<String, YaErr>;
+}
+
+impl<F> TokenSource for F
+where
+    F: Fn() -> Result<String, YaErr> + Send + Sync,
+{
+    fn issue_token(&self) -> Result<String, YaErr> {
+        self()
+    }
+}
+
+/// Shared `TokenSource` of a client
+pub type TokenProvider = Arc<dyn TokenSource>;
+
+/// Default `TokenSource` of clients built with a folder only: runs
+/// `yc iam create-token` and checks the output against the token pattern.
+/// Requires the `yandex-yc-cli` feature.
+#[derive(Clone, Debug)]
+pub struct YcCliTokenSource {
+    pattern: Regex,
+}
+
+impl YcCliTokenSource {
+    /// Checks the issued tokens against the given pattern instead of the
+    /// current Yandex one
+    pub fn with_pattern(pattern: &str) -> Result<Self, YaErr> {
+        Ok(Self {
+            pattern: Regex::new(pattern)
+                .map_err(|e| YaErr::BuildErr(format!("Invalid token pattern: {}", e)))?,
+        })
+    }
+}
+
+impl Default for YcCliTokenSource {
+    fn default() -> Self {
+        Self::with_pattern(YA_TOKEN_PATTERN).expect("default token pattern is valid")
+    }
+}
+
+impl TokenSource for YcCliTokenSource {
+    fn issue_token(&self) -> Result<String, YaErr> {
+        issue_token_cli(&self.pattern)
+    }
+}
+
+/// Yandex Cloud installation the OCR and IAM requests are sent to
+#[derive(PartialEq, Eq, Clone, Copy, Debug, Default)]
+pub enum Region {
+    /// Russian installation: `ocr.api.cloud.yandex.net`, IAM tokens from
+    /// `iam.api.cloud.yandex.net`
+    #[default]
+    Ru,
+    /// Kazakhstan installation (`kz1`): `ocr.api.yandexcloud.kz`, IAM tokens
+    /// from `iam.api.yandexcloud.kz`
+    Kz,
+}
+
+impl Region {
+    /// OCR API URL of the region
+    pub fn base_url(self) -> &'static str {
+        match self {
+            Region::Ru => YA_BASE_URL,
+            Region::Kz => YA_KZ_BASE_URL,
+        }
+    }
+
+    /// IAM token exchange URL of the region
+    pub fn iam_url(self) -> &'static str {
+        match self {
+            Region::Ru => YA_IAM_URL,
+            Region::Kz => YA_KZ_IAM_URL,
+        }
+    }
+}
+
+/// Which Yandex endpoint is used for recognition
+#[derive(PartialEq, Clone, Copy, Debug, Default)]
+pub enum RecognitionMode {
+    /// `/recognizeText`: single call, suited for small single-page images
+    Sync,
+    /// `/recognizeTextAsync` followed by `/getRecognition` polling
+    #[default]
+    Async,
+}
+
+/// Recognition models known to Yandex OCR. Any other identifier can still
+/// be passed as a string to `completion_model`.
+#[derive(PartialEq, Eq, Clone, Copy, Debug)]
+pub enum YandexModel {
+    /// General printed text, the default model
+    Page,
+    /// Printed text in multi-column layouts, read column by column
+    PageColumnSort,
+    /// Mixed printed and handwritten text
+    Handwritten,
+    /// Tables
+    Table,
+    /// Printed text returned as Markdown
+    Markdown,
+    /// Printed text and formulas returned as Markdown
+    MathMarkdown,
+    /// Passport main page
+    Passport,
+    /// Driver license, front side
+    DriverLicenseFront,
+    /// Driver license, back side
+    DriverLicenseBack,
+    /// Vehicle registration certificate, front side
+    VehicleRegistrationFront,
+    /// Vehicle registration certificate, back side
+    VehicleRegistrationBack,
+    /// Vehicle license plates
+    LicensePlates,
+}
+
+impl YandexModel {
+    /// Identifier sent in the `model` field of a recognition request
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            YandexModel::Page => "page",
+            YandexModel::PageColumnSort => "page-column-sort",
+            YandexModel::Handwritten => "handwritten",
+            YandexModel::Table => "table",
+            YandexModel::Markdown => "markdown",
+            YandexModel::MathMarkdown => "math-markdown",
+            YandexModel::Passport => "passport",
+            YandexModel::DriverLicenseFront => "driver-license-front",
+            YandexModel::DriverLicenseBack => "driver-license-back",
+            YandexModel::VehicleRegistrationFront => "vehicle-registration-front",
+            YandexModel::VehicleRegistrationBack => "vehicle-registration-back",
+            YandexModel::LicensePlates => "license-plates",
+        }
+    }
+}
+
+impl std::fmt::Display for YandexModel {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// What is put into the assistant text of a completion. The structured
+/// recognition is always available through `raw_response`.
+#[derive(PartialEq, Clone, Copy, Debug, Default)]
+pub enum TextOutput {
+    /// Only the recognized full text
+    #[default]
+    FullText,
+    /// Entities, markdown and full text joined into a single blob:
+    /// `ENTITIES:...\n\nMARKDOWN:...\n\nFULL_TEXT:...`. The entities are
+    /// given as JSON, the markdown and full text as they are.
+    Concatenated,
+    /// A single empty text, for callers only using the structured accessors
+    /// of `raw_response`; saves copying the text of large scans
+    Empty,
+}
+
+/// Optional artifacts Yandex produces along with the recognized text. A
+/// disabled artifact is removed from the response on the client side, so
+/// its accessors consistently return `None`. The flags are also sent with
+/// the request, but they are not part of the documented request schema of
+/// the OCR API and Yandex may ignore them, generating the artifact anyway.
+#[derive(PartialEq, Clone, Copy, Debug, Serialize, Deserialize)]
+#[serde(default)]
+pub struct OutputArtifacts {
+    #[serde(skip_serializing_if = "is_true")]
+    pub markdown: bool,
+    #[serde(skip_serializing_if = "is_true")]
+    pub tables: bool,
+    #[serde(skip_serializing_if = "is_true")]
+    pub entities: bool,
+}
+
+impl Default for OutputArtifacts {
+    fn default() -> Self {
+        Self {
+            markdown: true,
+            tables: true,
+            entities: true,
+        }
+    }
+}
+
+impl OutputArtifacts {
+    // drops the disabled artifacts in case Yandex generated them anyway
+    fn apply(&self, page: &mut ResultOcr) {
+        let ann = &mut page.text_ann;
+        if !self.markdown {
+            ann.markdown = None;
+        }
+        if !self.tables {
+            ann.tables = None;
+        }
+        if !self.entities {
+            ann.entities = None;
+        }
+    }
+}
+
+/// Hints on the scan quality of low-resolution documents. Unset hints are
+/// left out of the request. They are not part of the documented request
+/// schema of the OCR API, so Yandex may ignore them; check their effect
+/// with your endpoint first.
+#[derive(PartialEq, Eq, Clone, Copy, Debug, Default, Serialize, Deserialize)]
+pub struct QualityHints {
+    /// Resolution the document was scanned at, 50 to 1200 DPI
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub dpi: Option<u32>,
+}
+
+impl QualityHints {
+    fn validate(&self) -> Result<(), YaErr> {
+        match self.dpi {
+            Some(dpi) if !(50..=1200).contains(&dpi) => Err(YaErr::ReqErr(format!(
+                "DPI hint must be within 50..=1200, got {}",
+                dpi
+            ))),
+            _ => Ok(()),
+        }
+    }
+}
+
+// only the artifacts turned off are sent, keeping the default request as is
+fn is_true(value: &bool) -> bool {
+    *value
+}
+
+impl TextOutput {
+    fn render(&self, result: &ResultOcr) -> String {
+        let ann = &result.text_ann;
+        match self {
+            TextOutput::FullText => ann.full_text.clone(),
+            TextOutput::Concatenated => format!(
+                "ENTITIES:{}\n\nMARKDOWN:{}\n\nFULL_TEXT:{}",
+                serde_json::to_string(&ann.entities).unwrap_or_default(),
+                // serde_json::to_string(&ann.tables).unwrap_or_default(),
+                ann.markdown.as_deref().unwrap_or_default(),
+                ann.full_text,
+            ),
+            TextOutput::Empty => String::new(),
+        }
+    }
+}
+
+#[derive(Debug, thiserror::Error)]
 pub enum YaErr {
+    #[error("Error on Token update: {0}")]
     TokenUpdErr(String),
+    #[error("Error on construct: {0}")]
     BuildErr(String),
+    #[error("Error on request: {0}")]
     ReqErr(String),
+    #[error("Error on response parse: {0}")]
+    ParseErr(String),
+    #[error("Request timed out: {0}")]
+    TimeoutErr(String),
+    /// Transport failure, the source is kept to tell timeouts, connection
+    /// errors etc. apart
+    #[error("Error on request: {0}")]
+    HttpErr(#[from] reqwest::Error),
 }
 
-impl Display for YaErr {
-    fn fmt(&self, f: &mut Formatter) -> FmtResult {
-        match self {
-            YaErr::TokenUpdErr(e) => {
-                write!(f, "Error on Token update: {}", e)
-            }
-            YaErr::BuildErr(e) => {
-                write!(f, "Error on construct: {}", e)
-            }
-            YaErr::ReqErr(e) => {
-                write!(f, "Error on request: {}", e)
-            }
+// unreadable responses become `ResponseError`; everything else is boxed
+// into `RequestError`, so callers can downcast it to tell failed
+// authentication (`TokenUpdErr`) or timeouts apart
+impl From<YaErr> for CompletionError {
+    fn from(err: YaErr) -> Self {
+        match err {
+            YaErr::ParseErr(msg) => CompletionError::ResponseError(msg),
+            err => CompletionError::RequestError(Box::new(err)),
+        }
+    }
+}
+
+// temporary token, the moment it was issued and its expiry when known;
+// shared between clones of the client so a refresh done by one model is
+// visible to all of them
+#[derive(Debug, Default)]
+struct TokenState {
+    token: Option<String>,
+    token_upd: Option<NaiveDateTime>,
+    expires_at: Option<NaiveDateTime>,
+}
+
+impl TokenState {
+    // a token of known expiry is reissued `margin` before it, other ones
+    // every 3 hours
+    fn is_fresh(&self, now: NaiveDateTime, margin: TimeDelta) -> bool {
+        match (self.token_upd, self.expires_at, &self.token) {
+            (_, Some(expires_at), Some(_)) => now
+                .checked_add_signed(margin)
+                .is_some_and(|fresh_until| fresh_until < expires_at),
+            (Some(upd), None, Some(_)) => now - upd < YA_OCR_TOKEN_UPD,
+            _ => false,
         }
     }
 }
 
-impl Error for YaErr {}
+// the metadata service reports the lifetime of a token in seconds, one out
+// of the range of the clock is rejected rather than wrapped
+fn token_expiry(now: NaiveDateTime, expires_in: i64) -> Result<NaiveDateTime, YaErr> {
+    TimeDelta::try_seconds(expires_in)
+        .and_then(|lifetime| now.checked_add_signed(lifetime))
+        .ok_or_else(|| YaErr::ParseErr(format!("Token lifetime out of range: {}s", expires_in)))
+}
+
+#[derive(Debug, Serialize)]
+#[serde(untagged)]
+enum IamTokenRequest<'a> {
+    OAuth {
+        #[serde(rename = "yandexPassportOauthToken")]
+        oauth_token: &'a str,
+    },
+    Jwt {
+        jwt: String,
+    },
+}
+
+// authorized key of a service account as issued by
+// `yc iam key create --output key.json`
+#[derive(Clone, Deserialize)]
+#[cfg_attr(not(feature = "yandex-sa"), allow(dead_code))]
+struct SaKey {
+    id: String,
+    service_account_id: String,
+    private_key: String,
+}
+
+impl SaKey {
+    // reads the key file, checking right away that a JWT for the IAM
+    // endpoint can be signed with it to fail on construction rather than on
+    // the first request
+    fn from_file(path: &Path, iam_url: &str) -> Result<Self, YaErr> {
+        let raw = std::fs::read_to_string(path)
+            .map_err(|e| YaErr::BuildErr(format!("Could not read key file: {}", e)))?;
+        let sa_key: SaKey = serde_json::from_str(&raw)
+            .map_err(|e| YaErr::ParseErr(format!("Not valid key file: {}", e)))?;
+
+        sa_key
+            .jwt(iam_url)
+            .map_err(|e| YaErr::BuildErr(e.to_string()))?;
+        Ok(sa_key)
+    }
+}
+
+#[cfg(feature = "yandex-sa")]
+#[derive(Serialize)]
+struct SaJwtClaims<'a> {
+    aud: &'a str,
+    iss: &'a str,
+    iat: i64,
+    exp: i64,
+}
+
+#[cfg(feature = "yandex-sa")]
+impl SaKey {
+    // PS256-signed JWT valid for one hour, to be exchanged for an IAM token
+    fn jwt(&self, aud: &str) -> Result<String, YaErr> {
+        use jsonwebtoken::{Algorithm, EncodingKey, Header};
+
+        let mut header = Header::new(Algorithm::PS256);
+        header.kid = Some(self.id.clone());
+
+        // the key file prefixes the PEM with a "PLEASE DO NOT REMOVE" line
+        let pem = &self.private_key[self.private_key.find("-----BEGIN").unwrap_or(0)..];
+        let key = EncodingKey::from_rsa_pem(pem.as_bytes())
+            .map_err(|e| YaErr::TokenUpdErr(format!("Not valid private key: {}", e)))?;
+
+        let iat = chrono::Utc::now().timestamp();
+        let claims = SaJwtClaims {
+            aud,
+            iss: &self.service_account_id,
+            iat,
+            exp: iat + 3600,
+        };
+
+        jsonwebtoken::encode(&header, &claims, &key)
+            .map_err(|e| YaErr::TokenUpdErr(format!("Error on JWT signing: {}", e)))
+    }
+}
+
+#[cfg(not(feature = "yandex-sa"))]
+impl SaKey {
+    fn jwt(&self, _aud: &str) -> Result<String, YaErr> {
+        Err(YaErr::TokenUpdErr(
+            "Service account keys require the `yandex-sa` feature".to_string(),
+        ))
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct IamTokenResponse {
+    #[serde(rename = "iamToken")]
+    iam_token: String,
+    // RFC 3339
+    #[serde(rename = "expiresAt")]
+    expires_at: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct MetadataTokenResponse {
+    access_token: String,
+    // seconds
+    expires_in: i64,
+}
 
 // -------------------------------------------------//
 // Client                                           //
@@ -69,554 +546,4532 @@ impl Error for YaErr {}
 #[derive(Clone)]
 pub struct Client {
     base_url: String,
+    iam_url: String,
     api_key: Option<String>,
-    token: Option<String>,
+    oauth: Option<String>,
+    sa_key: Option<SaKey>,
+    token_provider: Option<TokenProvider>,
+    token: Arc<Mutex<TokenState>>,
     folder: Option<String>,
-    token_upd: Option<NaiveDateTime>,
-    rx: Regex,
     auth_t: AuthType,
     http_client: HttpClient,
-    pub langs: Vec<String>,
+    data_logging: bool,
+    token_margin: TimeDelta,
+    max_concurrent: usize,
+    // shared by the clones of the client, so the cap holds across completions
+    permits: Arc<Semaphore>,
+    default_headers: HeaderMap,
+    sync_path: String,
+    async_path: String,
+    poll_path: String,
+    langs: Vec<String>,
 }
 
-impl Client {
-    pub fn from_full(
-        a_base_url: Option<String>,     // optional
-        a_api_key: Option<String>,      // or use temp token
-        a_token: Option<String>,        // optional
-        a_folder: Option<String>,       // or use api_key
-        a_tkn_pattern: Option<&str>,    // optional
-        a_http_cli: Option<HttpClient>, // optional
-        a_langs: Option<Vec<String>>,   // ru by default
-    ) -> Result<Self, YaErr> {
+pub struct ClientBuilder<'a> {
+    base_url: &'a str,
+    iam_url: &'a str,
+    api_key: Option<&'a str>,
+    token: Option<&'a str>,
+    folder: Option<&'a str>,
+    oauth: Option<&'a str>,
+    token_provider: Option<TokenProvider>,
+    sa_key: Option<&'a Path>,
+    metadata: bool,
+    no_auth: bool,
+    token_pattern: &'a str,
+    http_client: Option<HttpClient>,
+    http_builder: Option<reqwest::ClientBuilder>,
+    languages: Option<Vec<String>>,
+    max_concurrent: usize,
+    pool: PoolConfig,
+    headers: Vec<(&'a str, &'a str)>,
+    sync_path: &'a str,
+    async_path: &'a str,
+    poll_path: &'a str,
+}
+
+// connection pool settings and User-Agent of the HTTP client built when
+// none is supplied; unset values keep the defaults
+#[derive(Clone, Debug, Default)]
+struct PoolConfig {
+    max_idle_per_host: Option<usize>,
+    idle_timeout: Option<time::Duration>,
+    tcp_keepalive: Option<time::Duration>,
+    user_agent: Option<String>,
+}
+
+impl PoolConfig {
+    fn http_client(&self) -> Result<HttpClient, YaErr> {
+        self.finish(HttpClient::builder().user_agent(YA_USER_AGENT))
+    }
+
+    // applies the settings on top of the ones made by the caller
+    fn finish(&self, mut builder: reqwest::ClientBuilder) -> Result<HttpClient, YaErr> {
+        if let Some(max_idle) = self.max_idle_per_host {
+            builder = builder.pool_max_idle_per_host(max_idle);
+        }
+        if let Some(timeout) = self.idle_timeout {
+            builder = builder.pool_idle_timeout(timeout);
+        }
+        if let Some(keepalive) = self.tcp_keepalive {
+            builder = builder.tcp_keepalive(keepalive);
+        }
+        if let Some(user_agent) = &self.user_agent {
+            builder = builder.user_agent(user_agent);
+        }
+        Ok(builder.build()?)
+    }
+}
+
+impl Default for ClientBuilder<'_> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<'a> ClientBuilder<'a> {
+    pub fn new() -> Self {
+        Self {
+            base_url: YA_BASE_URL,
+            iam_url: YA_IAM_URL,
+            api_key: None,
+            token: None,
+            folder: None,
+            oauth: None,
+            token_provider: None,
+            sa_key: None,
+            metadata: false,
+            no_auth: false,
+            token_pattern: YA_TOKEN_PATTERN,
+            http_client: None,
+            http_builder: None,
+            languages: None,
+            max_concurrent: YA_MAX_CONCURRENT,
+            pool: PoolConfig::default(),
+            headers: Vec::new(),
+            sync_path: YA_SYNC_PATH,
+            async_path: YA_ASYNC_PATH,
+            poll_path: YA_POLL_PATH,
+        }
+    }
+
+    /// Api-Key auth, takes precedence over the IAM token auth types
+    pub fn api_key(mut self, api_key: &'a str) -> Self {
+        self.api_key = Some(api_key);
+        self
+    }
+
+    /// Initial IAM token, reissued once it gets older than 3 hours
+    pub fn token(mut self, token: &'a str) -> Self {
+        self.token = Some(token);
+        self
+    }
+
+    /// Folder the IAM token auth types are billed to
+    pub fn folder(mut self, folder: &'a str) -> Self {
+        self.folder = Some(folder);
+        self
+    }
+
+    /// OAuth token exchanged natively for IAM tokens instead of calling the
+    /// `yc` CLI; requires a folder
+    pub fn oauth(mut self, oauth: &'a str) -> Self {
+        self.oauth = Some(oauth);
+        self
+    }
+
+    /// Issues IAM tokens with the given function instead of calling the
+    /// `yc` CLI; it is called again once the token gets older than 3 hours
+    pub fn token_provider(
+        self,
+        provider: impl Fn() -> Result<String, YaErr> + Send + Sync + 'static,
+    ) -> Self {
+        self.token_source(provider)
+    }
+
+    /// Issues IAM tokens with the given source instead of calling the `yc`
+    /// CLI; it is called again once the token gets older than 3 hours
+    pub fn token_source(mut self, source: impl TokenSource + 'static) -> Self {
+        self.token_provider = Some(Arc::new(source));
+        self
+    }
+
+    /// Authenticates with a service account authorized key file
+    /// (`yc iam key create --output key.json`). A JWT signed with the key is
+    /// exchanged for an IAM token on the first request and on every refresh.
+    /// Requires the `yandex-sa` feature.
+    pub fn service_account_key(mut self, path: &'a Path) -> Self {
+        self.sa_key = Some(path);
+        self
+    }
+
+    /// Reads IAM tokens of the service account attached to the Yandex
+    /// Compute instance from its metadata service. Tokens are refreshed once
+    /// they expire.
+    pub fn metadata(mut self) -> Self {
+        self.metadata = true;
+        self
+    }
+
+    /// Sends requests without any credentials when no other auth is set.
+    /// Meant for mock servers in tests or for proxies adding the credentials
+    /// themselves.
+    pub fn no_auth(mut self) -> Self {
+        self.no_auth = true;
+        self
+    }
+
+    /// Sends the OCR and IAM requests to the given region, `Region::Ru` by
+    /// default. A `base_url` set after it still overrides the OCR URL.
+    pub fn region(mut self, region: Region) -> Self {
+        self.base_url = region.base_url();
+        self.iam_url = region.iam_url();
+        self
+    }
+
+    pub fn base_url(mut self, base_url: &'a str) -> Self {
+        self.base_url = base_url;
+        self
+    }
+
+    /// Languages of the documents, `ru` by default. `*` lets Yandex detect
+    /// them, see `auto_detect_language`.
+    pub fn languages(mut self, languages: Vec<String>) -> Self {
+        self.languages = Some(languages);
+        self
+    }
+
+    /// Lets Yandex detect the languages of the documents instead of giving
+    /// them, for when they are not known upfront. The detected languages are
+    /// read back with `CompletionResponse::detected_languages`.
+    pub fn auto_detect_language(self) -> Self {
+        self.languages(vec![YA_AUTO_LANGUAGE.to_string()])
+    }
+
+    pub fn http_client(mut self, http_client: HttpClient) -> Self {
+        self.http_client = Some(http_client);
+        self
+    }
+
+    /// HTTP client builder with the caller's TLS, proxy or timeout settings,
+    /// finished with the pool settings of this builder. A complete
+    /// `http_client` takes precedence over it.
+    pub fn http_client_builder(mut self, builder: reqwest::ClientBuilder) -> Self {
+        self.http_builder = Some(builder);
+        self
+    }
+
+    /// Pattern the IAM tokens issued by the `yc` CLI are looked up with
+    pub fn token_pattern(mut self, token_pattern: &'a str) -> Self {
+        self.token_pattern = token_pattern;
+        self
+    }
+
+    /// Caps the documents the client and its clones recognize at once,
+    /// over all completions, 4 by default
+    pub fn max_concurrent(mut self, max_concurrent: usize) -> Self {
+        self.max_concurrent = max_concurrent.clamp(1, Semaphore::MAX_PERMITS);
+        self
+    }
+
+    /// Path the sync recognition requests are sent to, relative to the
+    /// base URL; `/recognizeText` by default
+    pub fn sync_path(mut self, path: &'a str) -> Self {
+        self.sync_path = path;
+        self
+    }
+
+    /// Path the async recognition requests are sent to, relative to the
+    /// base URL; `/recognizeTextAsync` by default. For gateways exposing
+    /// the OCR API under other paths.
+    pub fn async_path(mut self, path: &'a str) -> Self {
+        self.async_path = path;
+        self
+    }
+
+    /// Path the operations are polled at with their `operationId`,
+    /// `/getRecognition` by default
+    pub fn poll_path(mut self, path: &'a str) -> Self {
+        self.poll_path = path;
+        self
+    }
+
+    /// Header sent with every request, e.g. a tracing id expected by a
+    /// proxy. The auth headers set by the client take precedence over it.
+    pub fn header(mut self, name: &'a str, value: &'a str) -> Self {
+        self.headers.push((name, value));
+        self
+    }
+
+    /// Maximum idle connections kept per host. Like the other pool settings
+    /// it is ignored when a custom `http_client` is given.
+    pub fn pool_max_idle_per_host(mut self, max_idle: usize) -> Self {
+        self.pool.max_idle_per_host = Some(max_idle);
+        self
+    }
+
+    /// How long idle connections are kept in the pool
+    pub fn pool_idle_timeout(mut self, timeout: time::Duration) -> Self {
+        self.pool.idle_timeout = Some(timeout);
+        self
+    }
+
+    /// Interval of the TCP keep-alive probes of pooled connections
+    pub fn tcp_keepalive(mut self, interval: time::Duration) -> Self {
+        self.pool.tcp_keepalive = Some(interval);
+        self
+    }
+
+    /// `User-Agent` of the requests, `rig-yandex-ocr/<version>` by default.
+    /// It also replaces the one set on an `http_client_builder`, while a
+    /// complete `http_client` is sent as is.
+    pub fn user_agent(mut self, user_agent: &str) -> Self {
+        self.pool.user_agent = Some(user_agent.to_string());
+        self
+    }
+
+    pub fn build(self) -> Result<Client, YaErr> {
         // deduction of authh type
-        let auth_t = if a_api_key.is_some() {
+        let auth_t = if self.api_key.is_some() {
             AuthType::ApiKey
-        } else if a_folder.is_some() {
+        } else if self.token_provider.is_some() {
+            AuthType::Provider
+        } else if self.sa_key.is_some() {
+            AuthType::ServiceAccount
+        } else if self.metadata {
+            AuthType::Metadata
+        } else if self.folder.is_some() && self.oauth.is_some() {
+            AuthType::OAuth
+        } else if self.folder.is_some() {
             AuthType::Token
         } else {
             AuthType::None
         };
 
-        match auth_t {
-            AuthType::None => {
-                return Err(YaErr::BuildErr(
-                    "Incorrect auth details: need Api-Key or folder id".to_string(),
-                ));
+        if auth_t == AuthType::None && !self.no_auth {
+            return Err(YaErr::BuildErr(
+                "Incorrect auth details: need Api-Key, token provider or folder id".to_string(),
+            ));
+        }
+
+        let sa_key = match self.sa_key {
+            Some(path) if auth_t == AuthType::ServiceAccount => {
+                Some(SaKey::from_file(path, self.iam_url)?)
             }
-            _ => {}
+            _ => None,
+        };
+
+        let http_client = match (self.http_client, self.http_builder) {
+            (Some(http_client), _) => http_client,
+            (None, Some(builder)) => self.pool.finish(builder)?,
+            (None, None) => self.pool.http_client()?,
+        };
+
+        // the pattern is checked whatever the auth type to fail fast on typos
+        let cli_source = YcCliTokenSource::with_pattern(self.token_pattern)?;
+        let token_provider = match auth_t {
+            AuthType::Token => Some(Arc::new(cli_source) as TokenProvider),
+            _ => self.token_provider,
+        };
+
+        let mut default_headers = HeaderMap::new();
+        for (name, value) in &self.headers {
+            let name = HeaderName::from_bytes(name.as_bytes())
+                .map_err(|e| YaErr::BuildErr(format!("Invalid header name {}: {}", name, e)))?;
+            let value = HeaderValue::from_str(value)
+                .map_err(|e| YaErr::BuildErr(format!("Invalid value of header {}: {}", name, e)))?;
+            default_headers.append(name, value);
         }
 
-        let http_client = if let Some(http_client) = a_http_cli {
-            http_client
-        } else {
-            HttpClient::builder()
-                .build()
-                .expect("Not valid http client")
+        let out = Client {
+            base_url: self.base_url.to_string(),
+            iam_url: self.iam_url.to_string(),
+            api_key: self.api_key.map(str::to_string),
+            oauth: self.oauth.map(str::to_string),
+            sa_key,
+            token_provider,
+            token: Arc::new(Mutex::new(TokenState {
+                token_upd: self.token.map(|_| Local::now().naive_local()),
+                token: self.token.map(str::to_string),
+                expires_at: None,
+            })),
+            folder: self.folder.map(str::to_string),
+            auth_t: auth_t.clone(),
+            http_client,
+            data_logging: false,
+            token_margin: YA_TOKEN_REFRESH_MARGIN,
+            max_concurrent: self.max_concurrent,
+            permits: Arc::new(Semaphore::new(self.max_concurrent)),
+            default_headers,
+            sync_path: self.sync_path.to_string(),
+            async_path: self.async_path.to_string(),
+            poll_path: self.poll_path.to_string(),
+            langs: validate_languages(&self.languages.unwrap_or(vec!["ru".to_string()])).map_err(
+                |e| match e {
+                    YaErr::ReqErr(msg) => YaErr::BuildErr(msg),
+                    other => other,
+                },
+            )?,
         };
 
-        let mut out = Self {
-            base_url: a_base_url.unwrap_or(YA_BASE_URL.to_string()),
-            api_key: a_api_key,
-            token: a_token.clone(),
-            folder: a_folder,
-            token_upd: if a_token.is_some() {
-                Some(Local::now().naive_local())
-            } else {
-                None
+        // the native IAM exchange is done lazily on the first request, the
+        // CLI one right away to keep failing fast on a missing `yc`
+        if out.auth_t == AuthType::Token {
+            let mut state = out
+                .token
+                .try_lock()
+                .map_err(|e| YaErr::BuildErr(format!("Token state is busy: {}", e)))?;
+            if !state.is_fresh(Local::now().naive_local(), out.token_margin) {
+                out.upd_token_blocking(&mut state)?;
+            }
+        }
+
+        tracing::trace!("Created Ocr with params: {:?}", out);
+
+        Ok(out)
+    }
+}
+
+impl Client {
+    /// Create a new Yandex OCR client builder.
+    ///
+    /// # Example
+    /// ```
+    /// use rig::providers::yandex::client::Client;
+    ///
+    /// let yandex = Client::builder()
+    ///     .folder("your-folder-id")
+    ///     .oauth("your-oauth-token")
+    ///     .build()?;
+    /// # Ok::<(), rig::providers::yandex::client::YaErr>(())
+    /// ```
+    pub fn builder<'a>() -> ClientBuilder<'a> {
+        ClientBuilder::new()
+    }
+
+    pub fn from_full(
+        a_base_url: Option<String>,     // optional
+        a_api_key: Option<String>,      // or use temp token
+        a_token: Option<String>,        // optional
+        a_folder: Option<String>,       // or use api_key
+        a_tkn_pattern: Option<&str>,    // optional
+        a_http_cli: Option<HttpClient>, // optional
+        a_langs: Option<Vec<String>>,   // ru by default
+    ) -> Result<Self, YaErr> {
+        ClientBuilder {
+            base_url: a_base_url.as_deref().unwrap_or(YA_BASE_URL),
+            iam_url: YA_IAM_URL,
+            api_key: a_api_key.as_deref(),
+            token: a_token.as_deref(),
+            folder: a_folder.as_deref(),
+            oauth: None,
+            token_provider: None,
+            sa_key: None,
+            metadata: false,
+            no_auth: false,
+            token_pattern: a_tkn_pattern.unwrap_or(YA_TOKEN_PATTERN),
+            http_client: a_http_cli,
+            http_builder: None,
+            languages: a_langs,
+            max_concurrent: YA_MAX_CONCURRENT,
+            pool: PoolConfig::default(),
+            headers: Vec::new(),
+            sync_path: YA_SYNC_PATH,
+            async_path: YA_ASYNC_PATH,
+            poll_path: YA_POLL_PATH,
+        }
+        .build()
+    }
+
+    /// Same as `from_full`, but takes an HTTP client builder the crate
+    /// finishes instead of a complete client
+    pub fn from_full_with_http_builder(
+        a_base_url: Option<String>,
+        a_api_key: Option<String>,
+        a_token: Option<String>,
+        a_folder: Option<String>,
+        a_tkn_pattern: Option<&str>,
+        a_http_bld: reqwest::ClientBuilder,
+        a_langs: Option<Vec<String>>,
+    ) -> Result<Self, YaErr> {
+        let http_client = PoolConfig::default().finish(a_http_bld)?;
+        Self::from_full(
+            a_base_url,
+            a_api_key,
+            a_token,
+            a_folder,
+            a_tkn_pattern,
+            Some(http_client),
+            a_langs,
+        )
+    }
+
+    #[deprecated(note = "panics when the token can't be issued, use `try_from_fldr`")]
+    pub fn from_fldr(a_fldr: &str) -> Self {
+        Self::try_from_fldr(a_fldr).expect("Could not build Yandex client")
+    }
+
+    #[deprecated(note = "panics on invalid settings, use `try_from_api`")]
+    pub fn from_api(a_api: &str) -> Self {
+        Self::try_from_api(a_api).expect("Could not build Yandex client")
+    }
+
+    /// Creates a client for the folder, issuing IAM tokens with the `yc` CLI
+    /// (`yandex-yc-cli` feature, on by default)
+    pub fn try_from_fldr(a_fldr: &str) -> Result<Self, YaErr> {
+        Self::builder().folder(a_fldr).build()
+    }
+
+    /// Creates a client authenticated with an Api-Key
+    pub fn try_from_api(a_api: &str) -> Result<Self, YaErr> {
+        Self::builder().api_key(a_api).build()
+    }
+
+    /// Creates a client for the folder, exchanging the OAuth token for IAM
+    /// tokens with the IAM API instead of the `yc` CLI
+    pub fn try_from_oauth(a_fldr: &str, a_oauth: &str) -> Result<Self, YaErr> {
+        Self::builder().folder(a_fldr).oauth(a_oauth).build()
+    }
+
+    /// Authenticates with a service account authorized key file
+    /// (`yc iam key create --output key.json`). A JWT signed with the key is
+    /// exchanged for an IAM token on the first request and on every refresh.
+    /// Requires the `yandex-sa` feature. Use
+    /// `ClientBuilder::service_account_key` to change the other settings.
+    pub fn from_sa_key(path: &Path) -> Result<Self, YaErr> {
+        Self::builder().service_account_key(path).build()
+    }
+
+    pub fn new(api_key: &str) -> Self {
+        Self::try_from_api(api_key).expect("Could not create Yandex OCR")
+    }
+
+    /// Sends the OCR and IAM requests to the given region, e.g. for a client
+    /// made with `from_sa_key`
+    pub fn region(mut self, region: Region) -> Self {
+        self.base_url = region.base_url().to_string();
+        self.iam_url = region.iam_url().to_string();
+        self
+    }
+
+    /// Sets the OCR API URL, `CompletionModel::base_url` takes precedence
+    /// over it
+    pub fn base_url(mut self, base_url: &str) -> Self {
+        self.base_url = base_url.to_string();
+        self
+    }
+
+    pub fn custom_client(mut self, client: reqwest::Client) -> Self {
+        self.http_client = client;
+        self
+    }
+
+    /// Creates a model for one of the known recognition models, see
+    /// `completion_model` for arbitrary identifiers
+    pub fn completion_model_typed(&self, model: YandexModel) -> CompletionModel {
+        self.completion_model(model.as_str())
+    }
+
+    /// Sets how long before their expiry IAM tokens are reissued, 5 minutes
+    /// by default. Only applies to the auth types learning the expiry along
+    /// with the token (OAuth, service account, metadata service); tokens of
+    /// the `yc` CLI and custom providers are reissued every 3 hours.
+    pub fn token_refresh_margin(mut self, margin: time::Duration) -> Self {
+        self.token_margin = TimeDelta::from_std(margin).unwrap_or(TimeDelta::MAX);
+        self
+    }
+
+    /// Sets the `x-data-logging-enabled` header sent with every request.
+    /// When enabled Yandex may store the submitted documents and results to
+    /// improve its services, so keep it off (the default) for confidential
+    /// content.
+    pub fn data_logging(mut self, enabled: bool) -> Self {
+        self.data_logging = enabled;
+        self
+    }
+
+    /// Caps the documents the client and its clones recognize at once,
+    /// over all completions, 4 by default. Lower it to leave headroom for
+    /// the rate limits of the folder. The client gets a cap of its own,
+    /// no longer shared with the clones made before.
+    pub fn max_concurrent(mut self, max_concurrent: usize) -> Self {
+        self.max_concurrent = max_concurrent.clamp(1, Semaphore::MAX_PERMITS);
+        self.permits = Arc::new(Semaphore::new(self.max_concurrent));
+        self
+    }
+
+    // waits for one of the `max_concurrent` recognition slots
+    async fn acquire_permit(&self) -> Result<tokio::sync::SemaphorePermit<'_>, CompletionError> {
+        self.permits
+            .acquire()
+            .await
+            .map_err(|e| CompletionError::RequestError(Box::new(e)))
+    }
+
+    /// Default recognition languages of the models created by the client
+    pub fn languages(&self) -> &[String] {
+        &self.langs
+    }
+
+    /// Replaces the default recognition languages, validating them the same
+    /// way the builder does. The list must not be empty.
+    pub fn set_languages(&mut self, langs: Vec<String>) -> Result<(), YaErr> {
+        self.langs = validate_languages(&langs)?;
+        Ok(())
+    }
+
+    //================================================//
+    // Token upd                                      //
+    //================================================//
+    fn token_source(&self) -> Result<TokenProvider, YaErr> {
+        self.token_provider
+            .clone()
+            .ok_or_else(|| YaErr::TokenUpdErr("Token source is not set".to_string()))
+    }
+
+    // issues a new token through the token source, blocking until it is
+    // done; only meant for construction, async callers use `upd_token_source`
+    fn upd_token_blocking(&self, state: &mut TokenState) -> Result<(), YaErr> {
+        state.token = Some(self.token_source()?.issue_token()?);
+        state.token_upd = Some(Local::now().naive_local());
+        tracing::debug!("Token has been upgraded by the token source");
+
+        Ok(())
+    }
+
+    // runs the token source on the blocking thread pool
+    async fn upd_token_source(&self, state: &mut TokenState) -> Result<(), YaErr> {
+        let source = self.token_source()?;
+        let tkn = tokio::task::spawn_blocking(move || source.issue_token())
+            .await
+            .map_err(|e| YaErr::TokenUpdErr(format!("Error on token source task {}", e)))??;
+
+        state.token = Some(tkn);
+        state.token_upd = Some(Local::now().naive_local());
+        tracing::debug!("Token has been upgraded by the token source");
+
+        Ok(())
+    }
+
+    // exchanges the OAuth token or a service account JWT for an IAM token
+    // at the IAM endpoint
+    async fn upd_token_iam(&self, state: &mut TokenState) -> Result<(), YaErr> {
+        let body = match (&self.oauth, &self.sa_key) {
+            (_, Some(sa_key)) => IamTokenRequest::Jwt {
+                jwt: sa_key.jwt(&self.iam_url)?,
+            },
+            (Some(oauth_token), None) => IamTokenRequest::OAuth { oauth_token },
+            (None, None) => {
+                return Err(YaErr::TokenUpdErr(
+                    "Neither OAuth token nor service account key is set".to_string(),
+                ));
+            }
+        };
+
+        let res = self
+            .http_client
+            .post(&self.iam_url)
+            .json(&body)
+            .send()
+            .await
+            .map_err(|e| YaErr::TokenUpdErr(format!("Error on IAM request {}", e)))?;
+
+        if !res.status().is_success() {
+            return Err(YaErr::TokenUpdErr(format!(
+                "IAM responded with {}: {}",
+                res.status(),
+                res.text().await.unwrap_or_default()
+            )));
+        }
+
+        let tkn = res
+            .json::<IamTokenResponse>()
+            .await
+            .map_err(|e| YaErr::ParseErr(format!("Error on IAM response read {}", e)))?;
+
+        // without a parsable expiry the 3 hours window applies
+        let expires_at = tkn
+            .expires_at
+            .as_deref()
+            .and_then(|e| chrono::DateTime::parse_from_rfc3339(e).ok())
+            .map(|e| e.with_timezone(&Local).naive_local());
+
+        tracing::debug!(
+            "Token has been upgraded through IAM, expires at {:?}",
+            expires_at
+        );
+        state.token = Some(tkn.iam_token);
+        state.token_upd = Some(Local::now().naive_local());
+        state.expires_at = expires_at;
+
+        Ok(())
+    }
+
+    // reads the token of the instance service account from the metadata
+    // service
+    async fn upd_token_metadata(&self, state: &mut TokenState) -> Result<(), YaErr> {
+        let res = self
+            .http_client
+            .get(YA_METADATA_URL)
+            .header("Metadata-Flavor", "Google")
+            .send()
+            .await
+            .map_err(|e| YaErr::TokenUpdErr(format!("Error on metadata request {}", e)))?;
+
+        if !res.status().is_success() {
+            return Err(YaErr::TokenUpdErr(format!(
+                "Metadata service responded with {}: {}",
+                res.status(),
+                res.text().await.unwrap_or_default()
+            )));
+        }
+
+        let tkn = res
+            .json::<MetadataTokenResponse>()
+            .await
+            .map_err(|e| YaErr::ParseErr(format!("Error on metadata response read {}", e)))?;
+
+        tracing::debug!("Token has been upgraded through the metadata service");
+        let now = Local::now().naive_local();
+        let expires_at = token_expiry(now, tkn.expires_in)?;
+        state.token = Some(tkn.access_token);
+        state.token_upd = Some(now);
+        state.expires_at = Some(expires_at);
+
+        Ok(())
+    }
+
+    /// Reissues the IAM token if it is missing or stale. HTTP exchanges run
+    /// on the async client and the `yc` CLI on the blocking thread pool, so
+    /// the executor is never blocked. Requests refresh the token on their
+    /// own, this is useful to warm it up beforehand.
+    pub async fn upd_token_async(&self) -> Result<(), YaErr> {
+        let mut state = self.token.lock().await;
+        self.refresh_token(&mut state).await
+    }
+
+    async fn refresh_token(&self, state: &mut TokenState) -> Result<(), YaErr> {
+        if state.is_fresh(Local::now().naive_local(), self.token_margin) {
+            tracing::debug!(
+                "YaOcr::upd_token: not required to upd, last token updated {:?}",
+                state.token_upd
+            );
+            return Ok(());
+        }
+
+        match self.auth_t {
+            AuthType::OAuth | AuthType::ServiceAccount => self.upd_token_iam(state).await,
+            AuthType::Provider | AuthType::Token => self.upd_token_source(state).await,
+            AuthType::Metadata => self.upd_token_metadata(state).await,
+            AuthType::ApiKey | AuthType::None => Ok(()),
+        }
+    }
+
+    // refreshes the token if required and returns the current value
+    async fn current_token(&self) -> Result<String, YaErr> {
+        // holding the lock for the whole refresh makes concurrent callers
+        // wait for a single token instead of all requesting a new one
+        let mut state = self.token.lock().await;
+        self.refresh_token(&mut state).await?;
+
+        state
+            .token
+            .clone()
+            .ok_or_else(|| YaErr::TokenUpdErr("Token is not set".to_string()))
+    }
+}
+
+// asks the `yc` CLI for a new IAM token
+#[cfg(feature = "yandex-yc-cli")]
+fn issue_token_cli(rx: &Regex) -> Result<String, YaErr> {
+    let output = Command::new("bash")
+        .arg("-c")
+        .arg("yc iam create-token")
+        .output()
+        .map_err(|e| YaErr::TokenUpdErr(format!("Error on bash script {}", e)))?;
+
+    let mut tkn = match String::from_utf8(output.stdout) {
+        Ok(t) => t,
+        Err(e) => {
+            return Err(YaErr::TokenUpdErr(format!("Error on stdout read {}", e)));
+        }
+    };
+
+    tkn.pop();
+
+    if !rx.is_match(tkn.as_str()) {
+        return Err(YaErr::TokenUpdErr(format!("Not valid token: {}", tkn)));
+    }
+
+    Ok(tkn)
+}
+
+#[cfg(not(feature = "yandex-yc-cli"))]
+fn issue_token_cli(_rx: &Regex) -> Result<String, YaErr> {
+    Err(YaErr::TokenUpdErr(
+        "Issuing tokens with the yc CLI requires the `yandex-yc-cli` feature".to_string(),
+    ))
+}
+
+// joins with exactly one slash, whether or not the base ends and the path
+// starts with one
+fn join_url(base_url: &str, path: &str) -> String {
+    format!(
+        "{}/{}",
+        base_url.trim_end_matches('/'),
+        path.trim_start_matches('/')
+    )
+}
+
+// trims the language codes and checks that they look like ISO 639 codes
+// with optional BCP-47 subtags (`ru`, `en`, `zh-Hans`)
+fn validate_languages(langs: &[String]) -> Result<Vec<String>, YaErr> {
+    if langs.is_empty() {
+        return Err(YaErr::ReqErr("Language list is empty".to_string()));
+    }
+
+    langs
+        .iter()
+        .map(|l| {
+            let code = l.trim();
+            if code == YA_AUTO_LANGUAGE {
+                return Ok(code.to_string());
+            }
+
+            let mut parts = code.split('-');
+            let primary = parts.next().unwrap_or_default();
+            let valid = (2..=3).contains(&primary.len())
+                && primary.chars().all(|c| c.is_ascii_alphabetic())
+                && parts.all(|p| {
+                    (1..=8).contains(&p.len()) && p.chars().all(|c| c.is_ascii_alphanumeric())
+                });
+
+            if valid {
+                Ok(code.to_string())
+            } else {
+                Err(YaErr::ReqErr(format!("Not valid language code: {:?}", l)))
+            }
+        })
+        .collect()
+}
+
+impl std::fmt::Debug for Client {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Client")
+            .field("base_url", &self.base_url)
+            .field("iam_url", &self.iam_url)
+            .field("http_client", &self.http_client)
+            .field("data_logging", &self.data_logging)
+            .field("max_concurrent", &self.max_concurrent)
+            .field("api_key", &"<REDACTED>")
+            .finish()
+    }
+}
+
+impl Client {
+    //-----------------------------------------------//
+    // get, post utilities                           //
+    //-----------------------------------------------//
+    pub async fn post(&self, path: &str) -> Result<reqwest::RequestBuilder, YaErr> {
+        self.request(reqwest::Method::POST, join_url(&self.base_url, path))
+            .await
+    }
+
+    pub async fn get(&self, path: &str) -> Result<reqwest::RequestBuilder, YaErr> {
+        self.request(reqwest::Method::GET, join_url(&self.base_url, path))
+            .await
+    }
+
+    // authenticated request to an absolute URL
+    async fn request(
+        &self,
+        method: reqwest::Method,
+        url: String,
+    ) -> Result<reqwest::RequestBuilder, YaErr> {
+        let data_logging = self.data_logging.to_string();
+        let mut bld = self
+            .http_client
+            .request(method, url)
+            .headers(self.default_headers.clone());
+
+        match self.auth_t {
+            AuthType::Token
+            | AuthType::OAuth
+            | AuthType::ServiceAccount
+            | AuthType::Provider
+            | AuthType::Metadata => {
+                let token = self.current_token().await?;
+
+                // service accounts default to their own folder
+                if let Some(folder) = &self.folder {
+                    bld = bld.header("x-folder-id", folder);
+                }
+
+                Ok(bld
+                    .header("x-data-logging-enabled", &data_logging)
+                    .bearer_auth(token))
+            }
+            AuthType::ApiKey => {
+                let api_key = self
+                    .api_key
+                    .as_deref()
+                    .ok_or_else(|| YaErr::ReqErr("Api-Key is not set".to_string()))?;
+
+                Ok(bld
+                    .header("x-data-logging-enabled", &data_logging)
+                    .header("Authorization", format!("Api-Key {}", api_key)))
+            }
+            AuthType::None => Ok(bld.header("x-data-logging-enabled", &data_logging)),
+        }
+    }
+}
+
+impl ProviderClient for Client {
+    /// Creates a client from the environment, in order of precedence:
+    /// 1. `YANDEX_API_KEY` - Api-Key auth, other variables are ignored;
+    /// 2. `YANDEX_FOLDER_ID` + `YANDEX_IAM_TOKEN` - IAM token auth; the token
+    ///    is reissued through the `yc` CLI once it gets old.
+    ///
+    /// Panics if neither is set.
+    fn from_env() -> Self {
+        if let Ok(api_key) = std::env::var("YANDEX_API_KEY") {
+            return Self::new(&api_key);
+        }
+
+        let (Ok(folder), Ok(token)) = (
+            std::env::var("YANDEX_FOLDER_ID"),
+            std::env::var("YANDEX_IAM_TOKEN"),
+        ) else {
+            panic!("YANDEX_API_KEY or YANDEX_FOLDER_ID with YANDEX_IAM_TOKEN not set");
+        };
+
+        Self::from_full(None, None, Some(token), Some(folder), None, None, None)
+            .expect("Could not build Yandex client")
+    }
+
+    fn from_val(input: rig::client::ProviderValue) -> Self {
+        let rig::client::ProviderValue::Simple(api_key) = input else {
+            panic!("Incorrect provider value type")
+        };
+        Self::new(&api_key)
+    }
+}
+
+impl CompletionClient for Client {
+    type CompletionModel = CompletionModel;
+
+    /// Creates a Yandex OCR model
+    fn completion_model(&self, model_name: &str) -> CompletionModel {
+        CompletionModel {
+            client: self.clone(),
+            model: model_name.to_string(),
+            poll_max_attempts: YA_POLL_MAX_ATTEMPTS,
+            poll_interval: YA_POLL_INTERVAL,
+            mode: RecognitionMode::default(),
+            text_output: TextOutput::default(),
+            fetch_timeout: YA_FETCH_TIMEOUT,
+            languages: None,
+            request_timeout: YA_REQUEST_TIMEOUT,
+            max_retries: YA_MAX_RETRIES,
+            artifacts: OutputArtifacts::default(),
+            quality: QualityHints::default(),
+            base_url: None,
+            #[cfg(feature = "pdf")]
+            pdf_chunk_pages: None,
+            #[cfg(feature = "yandex-gzip")]
+            gzip_min_bytes: None,
+        }
+    }
+}
+
+impl VerifyClient for Client {
+    /// Obtains a token for the IAM based auth types, then polls a
+    /// non-existent operation: any answer but 401/403 proves the
+    /// credentials are accepted.
+    // #[cfg_attr(feature = "worker", worker::send)]
+    async fn verify(&self) -> Result<(), VerifyError> {
+        let response = self
+            .get(&format!("{}?operationId=rig-verify", self.poll_path))
+            .await
+            .map_err(|e| match e {
+                YaErr::TokenUpdErr(_) => VerifyError::InvalidAuthentication,
+                e => VerifyError::ProviderError(e.to_string()),
+            })?
+            .timeout(YA_REQUEST_TIMEOUT)
+            .send()
+            .await
+            .map_err(|e| VerifyError::HttpError(http_client::Error::Instance(e.into())))?;
+
+        match response.status() {
+            reqwest::StatusCode::UNAUTHORIZED | reqwest::StatusCode::FORBIDDEN => {
+                Err(VerifyError::InvalidAuthentication)
+            }
+            status if status.is_server_error() => Err(VerifyError::ProviderError(
+                response
+                    .text()
+                    .await
+                    .unwrap_or("Not known error".to_string()),
+            )),
+            _ => Ok(()),
+        }
+    }
+}
+
+impl_conversion_traits!(
+    AsEmbeddings,
+    AsTranscription,
+    AsImageGeneration,
+    AsAudioGeneration for Client
+);
+
+/// An error returned by the Yandex API, either wrapped in the
+/// `{"error": {...}}` envelope or given flat
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+enum ApiErrorResponse {
+    Envelope { error: ApiError },
+    Flat(ApiError),
+}
+
+#[derive(Debug, Deserialize)]
+struct ApiError {
+    #[serde(default)]
+    code: Option<serde_json::Value>,
+    message: String,
+    #[serde(default)]
+    details: Vec<serde_json::Value>,
+}
+
+impl ApiErrorResponse {
+    fn into_error(self) -> ApiError {
+        match self {
+            ApiErrorResponse::Envelope { error } | ApiErrorResponse::Flat(error) => error,
+        }
+    }
+}
+
+impl std::fmt::Display for ApiError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        // the message is sometimes empty, the details then tell what failed
+        let message = match (self.message.is_empty(), self.details.first()) {
+            (true, Some(detail)) => detail.to_string(),
+            (true, None) => "Not known error".to_string(),
+            (false, _) => self.message.clone(),
+        };
+
+        match &self.code {
+            Some(serde_json::Value::String(code)) => write!(f, "{} ({})", message, code),
+            Some(serde_json::Value::Number(code)) => write!(f, "{} ({})", message, code),
+            _ => write!(f, "{}", message),
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+enum ApiResponse<T> {
+    Ok(T),
+    Err(ApiErrorResponse),
+}
+
+impl From<ApiErrorResponse> for CompletionError {
+    fn from(err: ApiErrorResponse) -> Self {
+        CompletionError::ProviderError(err.into_error().to_string())
+    }
+}
+
+/// The response shape from the Yandex API
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct CompletionResponse {
+    /// Result of the first recognized page
+    pub result: ResultOcr,
+    /// Results of all recognized pages, document by document in request
+    /// order
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub results: Vec<ResultOcr>,
+    /// Async operations the pages were recognized by, for auditing; empty
+    /// for synchronous recognition
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub operations: Vec<AsyncRes>,
+    /// Time taken by the recognition and the polls it needed
+    #[serde(default)]
+    pub stats: RecognitionStats,
+}
+
+/// How long a recognition took and how many times its operations were
+/// polled; left at zero by `fetch`
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RecognitionStats {
+    pub elapsed: time::Duration,
+    /// `/getRecognition` requests of all the operations, 0 for synchronous
+    /// recognition
+    pub poll_attempts: u32,
+}
+
+impl CompletionResponse {
+    /// Parsed recognition result
+    pub fn result_ocr(&self) -> &ResultOcr {
+        &self.result
+    }
+
+    /// Results of all recognized pages: a multi-page PDF yields one result
+    /// per page, documents follow each other in request order
+    pub fn pages(&self) -> &[ResultOcr] {
+        if self.results.is_empty() {
+            std::slice::from_ref(&self.result)
+        } else {
+            &self.results
+        }
+    }
+
+    /// Text annotation with blocks, tables and entities
+    pub fn annotation(&self) -> &Annotation {
+        &self.result.text_ann
+    }
+
+    /// Tables recognized on the first page
+    pub fn tables(&self) -> &[Table] {
+        self.result.text_ann.tables.as_deref().unwrap_or_default()
+    }
+
+    /// Non-text picture regions recognized on the first page, see
+    /// `Picture::region`
+    pub fn pictures(&self) -> &[Picture] {
+        self.result.text_ann.pictures.as_deref().unwrap_or_default()
+    }
+
+    /// Entities (named fields such as passport data) recognized on the first
+    /// page
+    pub fn entities(&self) -> &[Entity] {
+        self.result.text_ann.entities.as_deref().unwrap_or_default()
+    }
+
+    /// Text of the first entity with the given name, looked up on all pages
+    pub fn entity(&self, name: &str) -> Option<&str> {
+        self.pages()
+            .iter()
+            .flat_map(|p| p.text_ann.entities.as_deref().unwrap_or_default())
+            .find(|e| e.name == name)
+            .map(|e| e.text.as_str())
+    }
+
+    /// Recognized plain text of the first page
+    pub fn full_text(&self) -> &str {
+        &self.result.text_ann.full_text
+    }
+
+    /// Size of the first page in pixels as `(width, height)`; `None` when
+    /// Yandex did not report it
+    pub fn page_size(&self) -> Option<(u32, u32)> {
+        let ann = &self.result.text_ann;
+        let width = u32::try_from(ann.width?).ok()?;
+        let height = u32::try_from(ann.height?).ok()?;
+        Some((width, height))
+    }
+
+    /// Rotation of the first page in degrees Yandex applied before
+    /// recognizing it, to be undone when drawing over the scan; `None` when
+    /// not reported, as opposed to `Some(0)` for an upright page
+    pub fn page_rotation(&self) -> Option<i32> {
+        i32::try_from(self.result.text_ann.rotate?).ok()
+    }
+
+    /// Markdown rendering of the first page, if Yandex produced one
+    pub fn markdown(&self) -> Option<&str> {
+        self.result.text_ann.markdown.as_deref()
+    }
+
+    /// Words of the first page making up the entity at the given index of
+    /// `entities`
+    pub fn words_for_entity(&self, entity_idx: usize) -> Vec<&Word> {
+        self.result.text_ann.words_for_entity(entity_idx)
+    }
+
+    /// Words of all pages with their geometry, see `Word::rect`
+    pub fn words(&self) -> impl Iterator<Item = &Word> {
+        self.pages().iter().flat_map(|p| p.text_ann.words())
+    }
+
+    /// Blocks of all pages with the given layout type, given either as a
+    /// `LayoutType` or as its `layoutType` value such as
+    /// `LAYOUT_TYPE_TABLE`
+    pub fn blocks_of_type(&self, layout_type: impl Into<LayoutType>) -> Vec<&Block> {
+        let layout_type = layout_type.into();
+        self.pages()
+            .iter()
+            .flat_map(|p| p.text_ann.blocks.as_deref().unwrap_or_default())
+            .filter(|b| b.layout() == layout_type)
+            .collect()
+    }
+
+    /// Distinct languages Yandex detected in the blocks of all pages, the
+    /// most frequent first
+    pub fn detected_languages(&self) -> Vec<String> {
+        // counted in order of first appearance, which breaks the ties
+        let mut counts: Vec<(&str, usize)> = Vec::new();
+        let languages = self
+            .pages()
+            .iter()
+            .flat_map(|p| p.text_ann.blocks.as_deref().unwrap_or_default())
+            .flat_map(|b| &b.languages);
+        for language in languages {
+            let code = language.language_code.as_str();
+            // not a language, echoed back by some auto-detected blocks
+            if code.is_empty() || code == YA_AUTO_LANGUAGE {
+                continue;
+            }
+            match counts.iter_mut().find(|(c, _)| *c == code) {
+                Some((_, n)) => *n += 1,
+                None => counts.push((code, 1)),
+            }
+        }
+
+        counts.sort_by_key(|(_, n)| std::cmp::Reverse(*n));
+        counts.into_iter().map(|(c, _)| c.to_string()).collect()
+    }
+
+    /// Serializes the complete response, all pages with their blocks,
+    /// tables and entities, for archival. The output deserializes back into
+    /// a `CompletionResponse` with `serde_json::from_str`.
+    pub fn to_json(&self) -> serde_json::Result<String> {
+        serde_json::to_string(self)
+    }
+
+    /// Same as `to_json`, indented for reading
+    pub fn to_json_pretty(&self) -> serde_json::Result<String> {
+        serde_json::to_string_pretty(self)
+    }
+
+    // number of pages the response carries, if it carries anything at all
+    fn recognized_pages(&self) -> Option<u64> {
+        if !self.results.is_empty() {
+            return Some(self.results.len() as u64);
+        }
+
+        let has_blocks = self
+            .result
+            .text_ann
+            .blocks
+            .as_ref()
+            .is_some_and(|b| !b.is_empty());
+
+        if self.result.page.is_some() || has_blocks {
+            Some(1)
+        } else {
+            None
+        }
+    }
+
+    // response made of the pages of separately recognized documents
+    fn from_results(results: Vec<ResultOcr>) -> Result<Self, CompletionError> {
+        let result = results.first().cloned().ok_or_else(|| {
+            CompletionError::ResponseError("No pages were recognized".to_string())
+        })?;
+
+        Ok(Self {
+            result,
+            results,
+            operations: Vec::new(),
+            stats: RecognitionStats::default(),
+        })
+    }
+
+    // response made of recognized documents along with their operations,
+    // the recognition having begun at `started`
+    fn from_recognized(
+        recognized: Recognized,
+        started: time::Instant,
+    ) -> Result<Self, CompletionError> {
+        let mut response = Self::from_results(recognized.pages)?;
+        response.operations = recognized.operations;
+        response.stats = RecognitionStats {
+            elapsed: started.elapsed(),
+            poll_attempts: recognized.poll_attempts,
+        };
+        Ok(response)
+    }
+
+    // one assistant text per recognized page
+    fn into_completion(
+        self,
+        text_output: TextOutput,
+    ) -> Result<completion::CompletionResponse<CompletionResponse>, CompletionError> {
+        let choice = if self.results.is_empty() || text_output == TextOutput::Empty {
+            OneOrMany::one(AssistantContent::text(text_output.render(&self.result)))
+        } else {
+            OneOrMany::many(
+                self.results
+                    .iter()
+                    .map(|r| AssistantContent::text(text_output.render(r))),
+            )
+            .map_err(|e| CompletionError::ResponseError(e.to_string()))?
+        };
+        let usage = self.token_usage().unwrap_or_default();
+
+        Ok(completion::CompletionResponse {
+            choice,
+            usage,
+            raw_response: self,
+        })
+    }
+}
+
+/// Concise summary of the recognition for logs, the derived `Debug` stays
+/// available for the full detail
+impl std::fmt::Display for CompletionResponse {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let pages = self.pages();
+        let count =
+            |f: fn(&Annotation) -> usize| -> usize { pages.iter().map(|p| f(&p.text_ann)).sum() };
+
+        write!(
+            f,
+            "{} page(s), {} chars, {} block(s), {} table(s), {} entities",
+            pages.len(),
+            count(|a| a.full_text.chars().count()),
+            count(|a| a.blocks.as_ref().map_or(0, Vec::len)),
+            count(|a| a.tables.as_ref().map_or(0, Vec::len)),
+            count(|a| a.entities.as_ref().map_or(0, Vec::len)),
+        )
+    }
+}
+
+/// The struct implementing the `CompletionModel` trait. Clones share the
+/// token state of the client, so a model can be cloned into every task or
+/// shared behind an `Arc`.
+///
+/// Of the `additional_params` of a completion request, `languageCodes`
+/// overrides the languages and any other key is sent along with every
+/// document as is, except the ones the crate sets itself: `mimeType`,
+/// `model`, `content`, `markdown`, `tables`, `entities` and `dpi`.
+#[derive(Clone)]
+pub struct CompletionModel {
+    pub client: Client,
+    pub model: String,
+    pub poll_max_attempts: usize,
+    pub poll_interval: time::Duration,
+    pub mode: RecognitionMode,
+    pub text_output: TextOutput,
+    pub fetch_timeout: time::Duration,
+    pub languages: Option<Vec<String>>,
+    pub request_timeout: time::Duration,
+    pub max_retries: usize,
+    pub artifacts: OutputArtifacts,
+    pub quality: QualityHints,
+    pub base_url: Option<String>,
+    #[cfg(feature = "pdf")]
+    pub pdf_chunk_pages: Option<usize>,
+    #[cfg(feature = "yandex-gzip")]
+    pub gzip_min_bytes: Option<usize>,
+}
+
+impl CompletionModel {
+    /// Sets how many times `/getRecognition` is polled and how long to wait
+    /// between attempts. The recognition is abandoned after roughly
+    /// `attempts * interval` (plus request latency), so large multi-page
+    /// documents may need a higher budget than the default 30 x 600ms.
+    pub fn poll_config(mut self, attempts: usize, interval: time::Duration) -> Self {
+        self.poll_max_attempts = attempts;
+        self.poll_interval = interval;
+        self
+    }
+
+    /// Selects the recognition endpoint. `RecognitionMode::Sync` avoids the
+    /// polling round-trips for small images; payloads over the sync size
+    /// limit are still sent asynchronously.
+    pub fn mode(mut self, mode: RecognitionMode) -> Self {
+        self.mode = mode;
+        self
+    }
+
+    /// Selects what goes into the assistant text. Use
+    /// `TextOutput::Concatenated` to get the legacy
+    /// `ENTITIES/MARKDOWN/FULL_TEXT` blob.
+    pub fn text_output(mut self, text_output: TextOutput) -> Self {
+        self.text_output = text_output;
+        self
+    }
+
+    /// Sets the timeout for downloading documents passed by URL
+    pub fn fetch_timeout(mut self, timeout: time::Duration) -> Self {
+        self.fetch_timeout = timeout;
+        self
+    }
+
+    /// Sets the timeout of every request to the OCR API: the recognition
+    /// request and each poll. Timeouts are reported as `YaErr::TimeoutErr`
+    /// wrapped into `CompletionError::RequestError`, so they can be told
+    /// apart and retried.
+    pub fn request_timeout(mut self, timeout: time::Duration) -> Self {
+        self.request_timeout = timeout;
+        self
+    }
+
+    /// Sets how many times a recognition request answered with 429 or 5xx
+    /// is retried. Other client errors are never retried.
+    pub fn max_retries(mut self, max_retries: usize) -> Self {
+        self.max_retries = max_retries;
+        self
+    }
+
+    /// Sends hints on the scan quality along with the documents, checked
+    /// when a request is made. The OCR API is not documented to read them,
+    /// see `QualityHints`.
+    pub fn quality(mut self, quality: QualityHints) -> Self {
+        self.quality = quality;
+        self
+    }
+
+    /// Selects which optional artifacts (Markdown, tables, entities) are
+    /// kept in the responses, all of them by default. The disabled ones are
+    /// trimmed client-side, see `OutputArtifacts`.
+    pub fn artifacts(mut self, artifacts: OutputArtifacts) -> Self {
+        self.artifacts = artifacts;
+        self
+    }
+
+    /// Sends the OCR requests of this model to the given URL instead of the
+    /// client one, e.g. to route models to different regions. Token
+    /// refreshes keep using their own endpoints.
+    pub fn base_url(mut self, base_url: &str) -> Self {
+        self.base_url = Some(base_url.to_string());
+        self
+    }
+
+    /// Splits PDFs of more than `pages` pages into chunks recognized as
+    /// separate async operations, Yandex rejecting documents above its page
+    /// limit. The pages of the chunks are merged back in document order.
+    /// Requires the `pdf` feature.
+    #[cfg(feature = "pdf")]
+    pub fn pdf_chunk_pages(mut self, pages: usize) -> Self {
+        self.pdf_chunk_pages = Some(pages.max(1));
+        self
+    }
+
+    /// Gzips the recognition requests whose JSON body has at least
+    /// `min_bytes`, sending them with `Content-Encoding: gzip`. Off by
+    /// default as the OCR API is not documented to accept compressed
+    /// requests; check it with your endpoint or proxy first. Requires the
+    /// `yandex-gzip` feature.
+    #[cfg(feature = "yandex-gzip")]
+    pub fn gzip_requests(mut self, min_bytes: usize) -> Self {
+        self.gzip_min_bytes = Some(min_bytes);
+        self
+    }
+
+    /// Overrides the client languages for requests made by this model
+    pub fn with_languages(mut self, languages: Vec<String>) -> Self {
+        self.languages = Some(languages);
+        self
+    }
+
+    // authenticated request to the model base URL if set, to the client one
+    // otherwise
+    async fn request(
+        &self,
+        method: reqwest::Method,
+        path: &str,
+    ) -> Result<reqwest::RequestBuilder, CompletionError> {
+        let base_url = self.base_url.as_deref().unwrap_or(&self.client.base_url);
+
+        Ok(self
+            .client
+            .request(method, join_url(base_url, path))
+            .await?
+            .timeout(self.request_timeout))
+    }
+
+    // options of a request read from its additional params. The languages
+    // come, in order of precedence, from `languageCodes`, the model override,
+    // the client default; the other keys are sent with every document unless
+    // the crate sets them itself.
+    fn request_options(
+        &self,
+        completion_request: &CompletionRequest,
+    ) -> Result<RequestOptions, CompletionError> {
+        let params = match &completion_request.additional_params {
+            Some(serde_json::Value::Object(params)) => params.clone(),
+            Some(serde_json::Value::Null) | None => serde_json::Map::new(),
+            Some(_) => {
+                return Err(
+                    YaErr::ReqErr("Additional params must be a JSON object".to_string()).into(),
+                );
+            }
+        };
+
+        let per_request = params
+            .get("languageCodes")
+            .map(|v| serde_json::from_value::<Vec<String>>(v.clone()))
+            .transpose()?;
+        let languages = per_request
+            .or_else(|| self.languages.clone())
+            .unwrap_or_else(|| self.client.langs.clone());
+
+        Ok(RequestOptions {
+            languages: validate_languages(&languages)?,
+            extra: params
+                .into_iter()
+                .filter(|(key, _)| !YA_REQUEST_KEYS.contains(&key.as_str()))
+                .collect(),
+        })
+    }
+
+    // options of requests made outside of a completion, without extra keys
+    fn default_options(&self) -> Result<RequestOptions, YaErr> {
+        let languages = self.languages.as_ref().unwrap_or(&self.client.langs);
+        Ok(RequestOptions {
+            languages: validate_languages(languages)?,
+            extra: serde_json::Map::new(),
+        })
+    }
+
+    // base64 content of a document, downloading it first if given by URL
+    async fn resolve_content(&self, data: DocumentSourceKind) -> Result<String, CompletionError> {
+        match data {
+            DocumentSourceKind::Base64(s) => Ok(s),
+            DocumentSourceKind::Url(url) => {
+                tracing::trace!("Yandex fetching document from {}", url);
+                let bytes = self
+                    .client
+                    .http_client
+                    .get(&url)
+                    .timeout(self.fetch_timeout)
+                    .send()
+                    .await
+                    .and_then(|r| r.error_for_status())
+                    .map_err(|e| CompletionError::RequestError(Box::new(e)))?
+                    .bytes()
+                    .await
+                    .map_err(|e| CompletionError::RequestError(Box::new(e)))?;
+
+                Ok(BASE64_STANDARD.encode(bytes))
+            }
+            _ => Err(YaErr::ReqErr("Should be base64 encoded or given by URL".to_string()).into()),
+        }
+    }
+
+    // documents and images of the most recent user message carrying any,
+    // along with their declared MIME types in message order; fails before
+    // any HTTP work when there is no such message
+    fn request_contents(
+        &self,
+        completion_request: &CompletionRequest,
+    ) -> Result<Vec<(DocumentSourceKind, Option<String>)>, CompletionError> {
+        // `OneOrMany` is never empty, but a history of blank prompts is as
+        // good as none
+        let is_blank = |m: &message::Message| match m {
+            message::Message::User { content } => content
+                .iter()
+                .all(|c| matches!(c, message::UserContent::Text(t) if t.text.trim().is_empty())),
+            message::Message::Assistant { content, .. } => content.iter().all(
+                |c| matches!(c, message::AssistantContent::Text(t) if t.text.trim().is_empty()),
+            ),
+        };
+        if completion_request.chat_history.iter().all(is_blank) {
+            return Err(YaErr::ReqErr("Empty chat history".to_string()).into());
+        }
+
+        let user_messages: Vec<_> = completion_request
+            .chat_history
+            .iter()
+            .filter_map(|m| match m {
+                message::Message::User { content } => Some(content),
+                _ => None,
+            })
+            .collect();
+
+        if user_messages.is_empty() {
+            return Err(
+                YaErr::ReqErr("Documents must be sent in a user message".to_string()).into(),
+            );
+        }
+
+        let is_attachment = |c: &message::UserContent| {
+            matches!(
+                c,
+                message::UserContent::Document(_) | message::UserContent::Image(_)
+            )
+        };
+        let Some(content) = user_messages
+            .into_iter()
+            .rev()
+            .find(|content| content.iter().any(is_attachment))
+        else {
+            return Err(YaErr::ReqErr(
+                "No user message has a document or image attached".to_string(),
+            )
+            .into());
+        };
+
+        // each attachment is recognized separately
+        let mut contents = Vec::new();
+        for item in content.iter() {
+            let (data, mime) = match item {
+                message::UserContent::Document(doc) => {
+                    (&doc.data, doc.media_type.as_ref().map(|m| m.to_mime_type()))
+                }
+                message::UserContent::Image(img) => {
+                    (&img.data, img.media_type.as_ref().map(|m| m.to_mime_type()))
+                }
+                _ => continue,
+            };
+            // a missing type is sniffed from the content once it is resolved
+            let mime = mime.map(checked_mime).transpose()?;
+            contents.push((data.clone(), mime));
+        }
+
+        Ok(contents)
+    }
+
+    // recognition request of a single document, its MIME type being
+    // sniffed from the content when not declared
+    async fn recognition_request(
+        &self,
+        data: DocumentSourceKind,
+        mime_type: Option<String>,
+        options: &RequestOptions,
+    ) -> Result<YaCompletionRequest, CompletionError> {
+        let content = self.resolve_content(data).await?;
+        let mime_type = match mime_type {
+            Some(mime_type) => mime_type,
+            None => sniff_mime(&content)
+                .ok_or_else(|| {
+                    YaErr::ReqErr(
+                        "Media type of the document is not set and could not be detected"
+                            .to_string(),
+                    )
+                })?
+                .to_string(),
+        };
+
+        self.quality.validate()?;
+        let request = YaCompletionRequest {
+            mime_type,
+            language_codes: options.languages.clone(),
+            model: self.model.clone(),
+            content,
+            artifacts: self.artifacts,
+            quality: self.quality,
+            extra: options.extra.clone(),
+        };
+
+        // the content is the document itself, only its size is logged
+        tracing::trace!(
+            "Yandex completion request: {} by {} in {:?}, {} bytes of content",
+            request.mime_type,
+            request.model,
+            request.language_codes,
+            request.content.len()
+        );
+
+        Ok(request)
+    }
+
+    /// Builds the recognition requests of a completion, one per attached
+    /// document, without sending them: a dry run showing the MIME types,
+    /// languages, model and content `completion` would send. Documents given
+    /// by URL are downloaded.
+    pub async fn build_request(
+        &self,
+        completion_request: &CompletionRequest,
+    ) -> Result<Vec<YaCompletionRequest>, CompletionError> {
+        let contents = self.request_contents(completion_request)?;
+        let options = self.request_options(completion_request)?;
+
+        let total = contents.len();
+        futures::future::try_join_all(contents.into_iter().enumerate().map(
+            |(i, (data, mime_type))| {
+                let options = &options;
+                async move {
+                    self.recognition_request(data, mime_type, options)
+                        .await
+                        .map_err(|e| document_error(e, i, total))
+                }
+            },
+        ))
+        .await
+    }
+
+    // recognizes a single document, returning its pages
+    async fn recognize_document(
+        &self,
+        request: YaCompletionRequest,
+    ) -> Result<Recognized, CompletionError> {
+        #[cfg(feature = "pdf")]
+        if let Some(chunk_pages) = self.pdf_chunk_pages
+            && request.mime_type == "application/pdf"
+        {
+            return self.recognize_pdf_chunks(request, chunk_pages).await;
+        }
+
+        self.recognize(&request).await
+    }
+
+    // recognizes a PDF longer than `chunk_pages` as separate async operations
+    // of at most `chunk_pages` pages each, numbering the pages of the chunks
+    // as in the whole document
+    #[cfg(feature = "pdf")]
+    async fn recognize_pdf_chunks(
+        &self,
+        request: YaCompletionRequest,
+        chunk_pages: usize,
+    ) -> Result<Recognized, CompletionError> {
+        let pdf = BASE64_STANDARD
+            .decode(&request.content)
+            .map_err(|e| CompletionError::RequestError(Box::new(e)))?;
+        let chunks = split_pdf(&pdf, chunk_pages)?;
+        if chunks.len() == 1 {
+            return self.recognize(&request).await;
+        }
+
+        let total = chunks.len();
+        let mut recognized = Recognized::default();
+        for (i, chunk) in chunks.into_iter().enumerate() {
+            tracing::debug!("Yandex recognizing PDF chunk {}/{}", i + 1, total);
+            let chunk_request = YaCompletionRequest {
+                content: BASE64_STANDARD.encode(chunk),
+                ..request.clone()
+            };
+
+            let first_page = i * chunk_pages;
+            let mut chunk = self
+                .recognize_with(&chunk_request, RecognitionMode::Async)
+                .await?;
+            for (n, page) in chunk.pages.iter_mut().enumerate() {
+                let in_chunk = page
+                    .page
+                    .as_deref()
+                    .and_then(|p| p.parse::<usize>().ok())
+                    .unwrap_or(n);
+                page.page = Some((first_page + in_chunk).to_string());
+            }
+            recognized.extend(chunk);
+        }
+
+        Ok(recognized)
+    }
+
+    // pages of a recognition result without the disabled artifacts
+    fn pages(&self, body: &str) -> Result<Vec<ResultOcr>, CompletionError> {
+        let mut pages = parse_pages(body)?;
+        for page in &mut pages {
+            self.artifacts.apply(page);
+        }
+
+        Ok(pages)
+    }
+
+    // sends the request to the endpoint matching the configured mode
+    async fn recognize(
+        &self,
+        request: &YaCompletionRequest,
+    ) -> Result<Recognized, CompletionError> {
+        self.recognize_with(request, self.mode).await
+    }
+
+    // sends the request to the endpoint matching the mode, within a span
+    // tying the init request, operation id and polls of the document together
+    async fn recognize_with(
+        &self,
+        request: &YaCompletionRequest,
+        mode: RecognitionMode,
+    ) -> Result<Recognized, CompletionError> {
+        let span = info_span!(
+            target: "rig::completions",
+            "yandex_ocr.recognize",
+            gen_ai.provider.name = "yandex",
+            gen_ai.request.model = self.model,
+            mime_type = request.mime_type,
+            operation_id = tracing::field::Empty,
+            attempts = tracing::field::Empty,
+            status = tracing::field::Empty,
+            elapsed_ms = tracing::field::Empty,
+        );
+        let started = time::Instant::now();
+
+        let result = async {
+            let content_len = decoded_len(&request.content);
+            if mode == RecognitionMode::Sync && content_len <= YA_SYNC_MAX_CONTENT_BYTES {
+                return Ok(Recognized {
+                    pages: self.recognize_sync(request).await?,
+                    ..Default::default()
+                });
+            }
+
+            if mode == RecognitionMode::Sync {
+                tracing::debug!(
+                    "Yandex content of {} bytes exceeds sync limit, falling back to async",
+                    content_len
+                );
+            }
+            self.recognize_async(request).await
+        }
+        .instrument(span.clone())
+        .await;
+
+        span.record("status", if result.is_ok() { "done" } else { "failed" });
+        span.record("elapsed_ms", started.elapsed().as_millis() as u64);
+        result
+    }
+
+    async fn recognize_sync(
+        &self,
+        request: &YaCompletionRequest,
+    ) -> Result<Vec<ResultOcr>, CompletionError> {
+        let body = self.request_body(request)?;
+        let response = body
+            .apply(
+                self.request(reqwest::Method::POST, &self.client.sync_path)
+                    .await?,
+            )
+            .send()
+            .await
+            .map_err(send_error)?;
+
+        if !response.status().is_success() {
+            return Err(status_error(response).await);
+        }
+
+        let t = response
+            .text()
+            .await
+            .map_err(|e| CompletionError::RequestError(e.into()))?;
+        tracing::trace!(target: "rig", "Yandex completion of {} bytes", t.len());
+
+        self.pages(&t)
+    }
+
+    // serialized recognition request, gzipped once it reaches the
+    // `gzip_requests` threshold
+    fn request_body(&self, request: &YaCompletionRequest) -> Result<RequestBody, CompletionError> {
+        check_content_size(&request.content, YA_MAX_CONTENT_BYTES)?;
+        let json = serde_json::to_vec(request)?;
+
+        #[cfg(feature = "yandex-gzip")]
+        if self
+            .gzip_min_bytes
+            .is_some_and(|min_bytes| json.len() >= min_bytes)
+        {
+            use std::io::Write;
+
+            let mut encoder =
+                flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+            let gzipped = encoder
+                .write_all(&json)
+                .and_then(|_| encoder.finish())
+                .map_err(|e| CompletionError::RequestError(Box::new(e)))?;
+            return Ok(RequestBody {
+                json: gzipped.into(),
+                gzip: true,
+            });
+        }
+
+        Ok(RequestBody {
+            json: json.into(),
+            gzip: false,
+        })
+    }
+
+    // sends the request to `/recognizeTextAsync`, retrying transient failures;
+    // every retry carries the same request id so that Yandex starts a single
+    // operation even if an earlier attempt got through
+    async fn start_async(
+        &self,
+        request: &YaCompletionRequest,
+        request_id: &str,
+    ) -> Result<AsyncRes, CompletionError> {
+        let body = self.request_body(request)?;
+        let mut retry = 0;
+        let response_init = loop {
+            let builder = self
+                .request(reqwest::Method::POST, &self.client.async_path)
+                .await?
+                .header("x-client-request-id", request_id)
+                .header("Idempotency-Key", request_id);
+            let response = body.apply(builder).send().await.map_err(send_error)?;
+
+            let status = response.status();
+            let retryable =
+                status == reqwest::StatusCode::TOO_MANY_REQUESTS || status.is_server_error();
+            if !retryable || retry >= self.max_retries {
+                break response;
+            }
+
+            let delay = retry_after(response.headers()).unwrap_or(
+                YA_RETRY_BACKOFF
+                    .saturating_mul(2u32.saturating_pow(retry as u32))
+                    .min(YA_MAX_RETRY_DELAY),
+            );
+            retry += 1;
+            tracing::debug!(
+                "Yandex recognition request got {}, retry {}/{} in {:?}",
+                status,
+                retry,
+                self.max_retries,
+                delay
+            );
+            tokio::time::sleep(delay).await;
+        };
+
+        if !response_init.status().is_success() {
+            return Err(status_error(response_init).await);
+        }
+
+        let t = response_init
+            .text()
+            .await
+            .map_err(|e| CompletionError::RequestError(e.into()))?;
+        tracing::trace!(target: "rig", "Yandex req echo of {} bytes", t.len());
+
+        Ok(serde_json::from_str::<AsyncRes>(&t)?)
+    }
+
+    // a single `/getRecognition` request
+    async fn poll_recognition(&self, operation_id: &str) -> Result<PollOutcome, CompletionError> {
+        let req = format!("{}?operationId={}", self.client.poll_path, operation_id);
+        tracing::trace!("Sending msg to get reeocg: {}", req);
+
+        let response = self
+            .request(reqwest::Method::GET, &req)
+            .await?
+            .send()
+            .await
+            .map_err(send_error)?;
+
+        // the bodies hold the recognized text, only their length is logged
+        let status = response.status();
+        if !status.is_success() {
+            let delay = retry_after(response.headers());
+            let t = response.text().await.unwrap_or("no_text".to_string());
+            tracing::debug!(
+                "Yandex poll of {} got {}, body of {} bytes",
+                operation_id,
+                status,
+                t.len()
+            );
+
+            // rate limits, server errors and the 404 answered until the
+            // operation is ready are waited out; other errors such as bad
+            // credentials or an unknown operation id are final
+            let not_ready = status == reqwest::StatusCode::NOT_FOUND
+                && error_message(&t).to_ascii_lowercase().contains("not ready");
+            if status == reqwest::StatusCode::TOO_MANY_REQUESTS
+                || status.is_server_error()
+                || not_ready
+            {
+                return Ok(PollOutcome::Pending(delay));
+            }
+            return Err(body_status_error(status, &t));
+        }
+
+        let t = response
+            .text()
+            .await
+            .map_err(|e| CompletionError::RequestError(e.into()))?;
+
+        // a running operation may be answered with its status instead of an
+        // error
+        let operation = serde_json::from_str::<OperationStatus>(&t).ok();
+        tracing::debug!(
+            "Yandex poll of {} got {}, done: {}, body of {} bytes",
+            operation_id,
+            status,
+            operation.as_ref().is_none_or(|o| o.done),
+            t.len()
+        );
+
+        if let Some(operation) = operation {
+            if !operation.done {
+                return Ok(PollOutcome::Pending(None));
+            }
+            // a finished operation carrying neither a result nor an error
+            // would otherwise fail to parse as one
+            if operation.result.is_none() && operation.error.is_none() {
+                return Err(CompletionError::ProviderError(
+                    "Operation finished without a recognition result".to_string(),
+                ));
+            }
+        }
+
+        Ok(PollOutcome::Done(self.pages(&t)?))
+    }
+
+    async fn recognize_async(
+        &self,
+        request: &YaCompletionRequest,
+    ) -> Result<Recognized, CompletionError> {
+        let request_id = uuid::Uuid::new_v4().to_string();
+        let operation = self.start_async(request, &request_id).await?;
+        self.poll_operation(operation).await
+    }
+
+    // polls a started operation until it is done or the attempts run out
+    async fn poll_operation(&self, mut operation: AsyncRes) -> Result<Recognized, CompletionError> {
+        let span = tracing::Span::current();
+        span.record("operation_id", operation.id.as_str());
+
+        for i in 0..self.poll_max_attempts {
+            tracing::trace!("Yandex {} attempt to get res", i + 1);
+            span.record("attempts", i + 1);
+
+            match self
+                .poll_recognition(&operation.id)
+                .await
+                .map_err(|e| operation_error(e, &operation))?
+            {
+                PollOutcome::Done(pages) => {
+                    operation.done = true;
+                    return Ok(Recognized {
+                        pages,
+                        operations: vec![operation],
+                        poll_attempts: i as u32 + 1,
+                    });
+                }
+                PollOutcome::Pending(delay) => {
+                    tokio::time::sleep(delay.unwrap_or(self.poll_interval)).await
+                }
+            }
+        }
+
+        Err(operation_error(
+            CompletionError::ProviderError("Could not get Async results".to_string()),
+            &operation,
+        ))
+    }
+
+    /// Waits for the results of an asynchronous operation started earlier,
+    /// e.g. by a process which crashed while polling it, skipping the
+    /// recognition request. Polls like `completion` does.
+    pub async fn resume(&self, operation_id: &str) -> Result<CompletionResponse, CompletionError> {
+        let started = time::Instant::now();
+        let span = info_span!(
+            target: "rig::completions",
+            "yandex_ocr.resume",
+            gen_ai.provider.name = "yandex",
+            gen_ai.request.model = self.model,
+            operation_id = operation_id,
+            attempts = tracing::field::Empty,
+        );
+        let operation = AsyncRes {
+            id: operation_id.to_string(),
+            description: "resumed".to_string(),
+            done: false,
+        };
+
+        let recognized = self.poll_operation(operation).instrument(span).await?;
+        CompletionResponse::from_recognized(recognized, started)
+    }
+
+    /// Submits the documents of the request for asynchronous recognition
+    /// without waiting for the results. The returned handle can be stored
+    /// and passed to `fetch` later, e.g. from another process.
+    pub async fn submit(
+        &self,
+        completion_request: CompletionRequest,
+    ) -> Result<OperationHandle, CompletionError> {
+        let requests = self.build_request(&completion_request).await?;
+
+        let total = requests.len();
+        let mut operation_ids = Vec::with_capacity(total);
+        let mut request_ids = Vec::with_capacity(total);
+        for (i, request) in requests.into_iter().enumerate() {
+            let request_id = uuid::Uuid::new_v4().to_string();
+            let operation = self
+                .start_async(&request, &request_id)
+                .await
+                .map_err(|e| document_error(e, i, total))?;
+            operation_ids.push(operation.id);
+            request_ids.push(request_id);
+        }
+
+        Ok(OperationHandle {
+            operation_ids,
+            request_ids,
+        })
+    }
+
+    /// Polls the operations of a submitted request once. Returns `None`
+    /// while any of them is still running.
+    pub async fn fetch(
+        &self,
+        handle: &OperationHandle,
+    ) -> Result<Option<CompletionResponse>, CompletionError> {
+        let total = handle.operation_ids.len();
+        let mut results = Vec::with_capacity(total);
+        for (i, id) in handle.operation_ids.iter().enumerate() {
+            match self
+                .poll_recognition(id)
+                .await
+                .map_err(|e| document_error(e, i, total))?
+            {
+                PollOutcome::Done(pages) => results.extend(pages),
+                PollOutcome::Pending(_) => return Ok(None),
+            }
+        }
+
+        CompletionResponse::from_results(results).map(Some)
+    }
+
+    /// Recognizes a document held as raw bytes, e.g. read from a file,
+    /// taking care of the base64 encoding. The model or client languages
+    /// are used.
+    pub async fn recognize_bytes(
+        &self,
+        bytes: &[u8],
+        mime: impl MimeType,
+    ) -> Result<CompletionResponse, CompletionError> {
+        self.recognize_raw(bytes, mime.to_mime_type()).await
+    }
+
+    /// Recognizes a JPEG, PNG or PDF file, the MIME type being inferred from
+    /// its extension. The model or client languages are used.
+    pub async fn recognize_file(&self, path: &Path) -> Result<CompletionResponse, CompletionError> {
+        let mime = mime_guess::from_path(path).first_raw().ok_or_else(|| {
+            CompletionError::from(YaErr::ReqErr(format!(
+                "Could not infer the media type of {}",
+                path.display()
+            )))
+        })?;
+        // fail on unsupported files before reading them
+        checked_mime(mime)?;
+
+        let owned_path = path.to_path_buf();
+        let bytes = tokio::task::spawn_blocking(move || std::fs::read(owned_path))
+            .await
+            .map_err(|e| CompletionError::RequestError(Box::new(e)))?
+            .map_err(|e| CompletionError::RequestError(Box::new(e)))?;
+
+        self.recognize_raw(&bytes, mime).await
+    }
+
+    async fn recognize_raw(
+        &self,
+        bytes: &[u8],
+        mime: &str,
+    ) -> Result<CompletionResponse, CompletionError> {
+        let started = time::Instant::now();
+        let options = self.default_options()?;
+
+        self.quality.validate()?;
+        let request = YaCompletionRequest {
+            mime_type: checked_mime(mime)?,
+            language_codes: options.languages,
+            model: self.model.clone(),
+            content: BASE64_STANDARD.encode(bytes),
+            artifacts: self.artifacts,
+            quality: self.quality,
+            extra: options.extra,
+        };
+
+        let _permit = self.client.acquire_permit().await?;
+        CompletionResponse::from_recognized(self.recognize(&request).await?, started)
+    }
+
+    /// Blocking counterpart of `completion` for code without an async
+    /// runtime, e.g. scripts. The recognition runs on a dedicated
+    /// single-threaded runtime, so calling it from async code is an error.
+    /// Requires the `yandex-blocking` feature.
+    #[cfg(feature = "yandex-blocking")]
+    pub fn recognize_blocking(
+        &self,
+        completion_request: CompletionRequest,
+    ) -> Result<completion::CompletionResponse<CompletionResponse>, CompletionError> {
+        use completion::CompletionModel as _;
+
+        if tokio::runtime::Handle::try_current().is_ok() {
+            return Err(YaErr::ReqErr(
+                "recognize_blocking can't be called from async code, use completion".to_string(),
+            )
+            .into());
+        }
+
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .map_err(|e| YaErr::ReqErr(format!("Could not start a runtime: {}", e)))?;
+        runtime.block_on(self.completion(completion_request))
+    }
+
+    /// Recognizes a batch of documents, yielding the response of every
+    /// document in the given order as soon as it is ready, so each one can
+    /// be processed and dropped before the batch is done. Up to the client
+    /// `max_concurrent` documents are recognized at once, counting those of
+    /// the other requests of the client.
+    pub fn recognize_stream(
+        &self,
+        docs: impl IntoIterator<Item = message::Document>,
+    ) -> impl futures::Stream<Item = Result<CompletionResponse, CompletionError>> + Send + 'static
+    {
+        let model = self.clone();
+        let docs: Vec<_> = docs.into_iter().collect();
+
+        futures::stream::iter(docs)
+            .map(move |doc| {
+                let model = model.clone();
+                async move {
+                    let _permit = model.client.acquire_permit().await?;
+                    model.recognize_doc(doc).await
+                }
+            })
+            .buffered(self.client.max_concurrent)
+    }
+
+    /// Recognizes a document into Markdown, keeping it even when the model
+    /// artifacts turn it off. The Markdown of the pages
+    /// is separated by an empty line. Fails when none was produced, e.g. by
+    /// a model without Markdown output; see `YandexModel::Markdown`.
+    pub async fn recognize_to_markdown(
+        &self,
+        doc: message::Document,
+    ) -> Result<String, CompletionError> {
+        let model = self.clone().artifacts(OutputArtifacts {
+            markdown: true,
+            ..self.artifacts
+        });
+        let _permit = self.client.acquire_permit().await?;
+        let response = model.recognize_doc(doc).await?;
+
+        let pages: Vec<&str> = response
+            .pages()
+            .iter()
+            .filter_map(|p| p.text_ann.markdown.as_deref())
+            .collect();
+        if pages.is_empty() {
+            return Err(CompletionError::ResponseError(format!(
+                "Model {} did not produce Markdown",
+                self.model
+            )));
+        }
+
+        Ok(pages.join("\n\n"))
+    }
+
+    // recognizes a single document with the model or client languages
+    async fn recognize_doc(
+        &self,
+        doc: message::Document,
+    ) -> Result<CompletionResponse, CompletionError> {
+        let started = time::Instant::now();
+        let mime = doc
+            .media_type
+            .as_ref()
+            .map(|m| checked_mime(m.to_mime_type()))
+            .transpose()?;
+        let options = self.default_options()?;
+        let request = self.recognition_request(doc.data, mime, &options).await?;
+
+        let recognized = self.recognize_document(request).await?;
+        CompletionResponse::from_recognized(recognized, started)
+    }
+}
+
+// pages of recognized documents along with the async operations they were
+// recognized by
+#[derive(Debug, Default)]
+struct Recognized {
+    pages: Vec<ResultOcr>,
+    operations: Vec<AsyncRes>,
+    poll_attempts: u32,
+}
+
+impl Recognized {
+    fn extend(&mut self, other: Recognized) {
+        self.pages.extend(other.pages);
+        self.operations.extend(other.operations);
+        self.poll_attempts += other.poll_attempts;
+    }
+}
+
+// JSON body of a recognition request, cheap to clone for the retries
+struct RequestBody {
+    json: bytes::Bytes,
+    gzip: bool,
+}
+
+impl RequestBody {
+    fn apply(&self, builder: reqwest::RequestBuilder) -> reqwest::RequestBuilder {
+        let builder = builder.header(reqwest::header::CONTENT_TYPE, "application/json");
+        let builder = if self.gzip {
+            builder.header(reqwest::header::CONTENT_ENCODING, "gzip")
+        } else {
+            builder
+        };
+        builder.body(self.json.clone())
+    }
+}
+
+// result of a single poll, a pending operation may carry the delay requested
+// by the server
+enum PollOutcome {
+    Done(Vec<ResultOcr>),
+    Pending(Option<time::Duration>),
+}
+
+// status of an operation returned by `/getRecognition` while it is running,
+// or once it is done without the result
+#[derive(Deserialize)]
+struct OperationStatus {
+    done: bool,
+    result: Option<serde::de::IgnoredAny>,
+    error: Option<serde::de::IgnoredAny>,
+}
+
+/// Identifies the recognition operations of a submitted request, one per
+/// document in request order
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct OperationHandle {
+    pub operation_ids: Vec<String>,
+    /// Ids the operations were started with, sent as `x-client-request-id`
+    /// and `Idempotency-Key`; handy when asking Yandex support about them
+    #[serde(default)]
+    pub request_ids: Vec<String>,
+}
+
+/// Recognition request of a single document as sent to Yandex, see
+/// `CompletionModel::build_request`
+#[derive(Clone, Debug, Serialize, Deserialize, Default)]
+pub struct YaCompletionRequest {
+    #[serde(rename = "mimeType")]
+    pub mime_type: String,
+
+    #[serde(rename = "languageCodes")]
+    pub language_codes: Vec<String>,
+    pub model: String,
+    /// Base64 encoded document
+    pub content: String,
+    #[serde(flatten)]
+    pub artifacts: OutputArtifacts,
+    #[serde(flatten)]
+    pub quality: QualityHints,
+    /// Additional params of the completion request
+    #[serde(flatten)]
+    pub extra: serde_json::Map<String, serde_json::Value>,
+}
+
+// per-request settings shared by the documents of a completion
+#[derive(Clone, Debug)]
+struct RequestOptions {
+    languages: Vec<String>,
+    extra: serde_json::Map<String, serde_json::Value>,
+}
+
+impl completion::CompletionModel for CompletionModel {
+    type Response = CompletionResponse;
+    type StreamingResponse = CompletionResponse;
+
+    // #[cfg_attr(feature = "worker", worker::send)]
+    async fn completion(
+        &self,
+        completion_request: CompletionRequest,
+    ) -> Result<completion::CompletionResponse<CompletionResponse>, rig::completion::CompletionError>
+    {
+        let started = time::Instant::now();
+        let requests = self.build_request(&completion_request).await?;
+
+        // the documents are recognized concurrently, the pages keep their
+        // order
+        let total = requests.len();
+        let documents = futures::future::try_join_all(requests.into_iter().enumerate().map(
+            |(i, request)| async move {
+                let _permit = self.client.acquire_permit().await?;
+                self.recognize_document(request)
+                    .await
+                    .map_err(|e| document_error(e, i, total))
+            },
+        ))
+        .await?;
+
+        let mut recognized = Recognized::default();
+        for document in documents {
+            recognized.extend(document);
+        }
+        CompletionResponse::from_recognized(recognized, started)?.into_completion(self.text_output)
+    }
+
+    /// Recognizes the documents one by one, yielding the text of their pages
+    /// as soon as the operation of a document completes, followed by the
+    /// aggregated response.
+    async fn stream(
+        &self,
+        completion_request: CompletionRequest,
+    ) -> Result<rig::streaming::StreamingCompletionResponse<Self::StreamingResponse>, CompletionError>
+    {
+        let started = time::Instant::now();
+        let requests = self.build_request(&completion_request).await?;
+        let model = self.clone();
+
+        let stream = stream! {
+            let total = requests.len();
+            let mut results = Recognized::default();
+            for (i, request) in requests.into_iter().enumerate() {
+                let document = match model.client.acquire_permit().await {
+                    Ok(_permit) => model.recognize_document(request).await,
+                    Err(e) => Err(e),
+                };
+                match document {
+                    Ok(document) => {
+                        for page in &document.pages {
+                            if model.text_output != TextOutput::Empty {
+                                let mut text = model.text_output.render(page);
+                                if !results.pages.is_empty() {
+                                    text.insert_str(0, "\n\n");
+                                }
+                                yield Ok(RawStreamingChoice::Message(text));
+                            }
+                        }
+                        results.extend(document);
+                    }
+                    Err(e) => {
+                        yield Err(document_error(e, i, total));
+                        return;
+                    }
+                }
+            }
+
+            match CompletionResponse::from_recognized(results, started) {
+                Ok(response) => yield Ok(RawStreamingChoice::FinalResponse(response)),
+                Err(e) => yield Err(e),
+            }
+        };
+
+        Ok(StreamingCompletionResponse::stream(Box::pin(stream)))
+    }
+}
+
+/// Whether the OCR API accepts documents of the given MIME type: JPEG, PNG
+/// or PDF
+pub fn supported_mime(mime: &str) -> bool {
+    YA_SUPPORTED_MIME
+        .iter()
+        .any(|m| m.eq_ignore_ascii_case(mime.trim()))
+}
+
+// MIME type of base64 content told by its magic bytes, for documents sent
+// without one
+fn sniff_mime(content: &str) -> Option<&'static str> {
+    // 12 base64 characters decode to the first 9 bytes
+    let prefix = content.get(..12).unwrap_or(content);
+    let bytes = BASE64_STANDARD.decode(prefix).ok()?;
+
+    if bytes.starts_with(&[0xFF, 0xD8]) {
+        Some("image/jpeg")
+    } else if bytes.starts_with(&[0x89, b'P', b'N', b'G']) {
+        Some("image/png")
+    } else if bytes.starts_with(b"%PDF") {
+        Some("application/pdf")
+    } else {
+        None
+    }
+}
+
+// size of base64 content once decoded; base64 inflates it by 4/3
+fn decoded_len(content: &str) -> usize {
+    let padding = content.bytes().rev().take_while(|&b| b == b'=').count();
+    (content.len() / 4 * 3).saturating_sub(padding)
+}
+
+// rejects base64 content decoding to more than `limit` bytes
+fn check_content_size(content: &str, limit: usize) -> Result<(), YaErr> {
+    let size = decoded_len(content);
+    if size > limit {
+        return Err(YaErr::ReqErr(format!(
+            "Document of {} bytes exceeds the {} bytes limit",
+            size, limit
+        )));
+    }
+
+    Ok(())
+}
+
+// MIME type string of a document, rejecting the types Yandex can't read
+fn checked_mime(mime: &str) -> Result<String, CompletionError> {
+    if !supported_mime(mime) {
+        return Err(YaErr::ReqErr(format!(
+            "Unsupported media type {}, expected one of {}",
+            mime,
+            YA_SUPPORTED_MIME.join(", ")
+        ))
+        .into());
+    }
+
+    Ok(mime.to_string())
+}
+
+// pages of a recognition result: a multi-page document is answered with one
+// JSON object per page, separated by newlines
+fn parse_pages(body: &str) -> Result<Vec<ResultOcr>, CompletionError> {
+    let mut pages = Vec::new();
+    for response in
+        serde_json::Deserializer::from_str(body).into_iter::<ApiResponse<CompletionResponse>>()
+    {
+        match response? {
+            ApiResponse::Ok(response) => pages.push(response.result),
+            ApiResponse::Err(err) => return Err(err.into()),
+        }
+    }
+
+    if pages.is_empty() {
+        return Err(CompletionError::ResponseError(
+            "Empty recognition result".to_string(),
+        ));
+    }
+
+    Ok(pages)
+}
+
+// delay requested by the server through the Retry-After header, given
+// either in seconds or as an HTTP-date, capped at `YA_MAX_RETRY_DELAY`
+fn retry_after(headers: &reqwest::header::HeaderMap) -> Option<time::Duration> {
+    requested_delay(headers).map(|delay| delay.min(YA_MAX_RETRY_DELAY))
+}
+
+fn requested_delay(headers: &reqwest::header::HeaderMap) -> Option<time::Duration> {
+    let value = headers
+        .get(reqwest::header::RETRY_AFTER)?
+        .to_str()
+        .ok()?
+        .trim();
+
+    if let Ok(secs) = value.parse::<u64>() {
+        return Some(time::Duration::from_secs(secs));
+    }
+
+    let date = chrono::DateTime::parse_from_rfc2822(value).ok()?;
+    // a date in the past means "retry right away"
+    Some(
+        (date.with_timezone(&chrono::Utc) - chrono::Utc::now())
+            .to_std()
+            .unwrap_or_default(),
+    )
+}
+
+// splits the PDF into documents of at most `chunk_pages` pages each, or
+// keeps it whole when it is short enough
+#[cfg(feature = "pdf")]
+fn split_pdf(pdf: &[u8], chunk_pages: usize) -> Result<Vec<Vec<u8>>, CompletionError> {
+    let document =
+        lopdf::Document::load_mem(pdf).map_err(|e| CompletionError::RequestError(Box::new(e)))?;
+    let total = document.get_pages().len() as u32;
+    let chunk_pages = chunk_pages as u32;
+    if total <= chunk_pages {
+        return Ok(vec![pdf.to_vec()]);
+    }
+
+    (1..=total)
+        .step_by(chunk_pages as usize)
+        .map(|first| {
+            let last = (first + chunk_pages - 1).min(total);
+            let others: Vec<u32> = (1..=total).filter(|p| *p < first || *p > last).collect();
+
+            let mut chunk = document.clone();
+            chunk.delete_pages(&others);
+            chunk.prune_objects();
+
+            let mut out = Vec::new();
+            chunk
+                .save_to(&mut out)
+                .map_err(|e| CompletionError::RequestError(Box::new(e)))?;
+            Ok(out)
+        })
+        .collect()
+}
+
+// reports the status code along with the Yandex error message, read from
+// the `{"error": {"code", "message"}}` envelope or its flat variant
+async fn status_error(response: reqwest::Response) -> CompletionError {
+    let status = response.status();
+    let body = response.text().await.unwrap_or_default();
+
+    body_status_error(status, &body)
+}
+
+// same as `status_error` for a response whose body has been read already
+fn body_status_error(status: reqwest::StatusCode, body: &str) -> CompletionError {
+    CompletionError::ProviderError(format!(
+        "Yandex API returned {}: {}",
+        status,
+        error_message(body)
+    ))
+}
+
+fn error_message(body: &str) -> String {
+    if let Ok(err) = serde_json::from_str::<ApiErrorResponse>(body) {
+        return err.into_error().to_string();
+    }
+
+    match body.trim() {
+        "" => "Not known error".to_string(),
+        text => text.to_string(),
+    }
+}
+
+// keeps timeouts distinguishable from other transport errors
+fn send_error(e: reqwest::Error) -> CompletionError {
+    if e.is_timeout() {
+        CompletionError::from(YaErr::TimeoutErr(e.to_string()))
+    } else {
+        CompletionError::RequestError(Box::new(e))
+    }
+}
+
+// names the failed operation along with its description, which often tells
+// what went wrong
+fn operation_error(e: CompletionError, operation: &AsyncRes) -> CompletionError {
+    match e {
+        CompletionError::ProviderError(msg) => CompletionError::ProviderError(format!(
+            "Operation {} ({}) failed: {}",
+            operation.id, operation.description, msg
+        )),
+        e => e,
+    }
+}
+
+// a single document keeps the original error untouched, otherwise the
+// failed document is named in the message of provider and response errors;
+// the typed request errors (timeouts, transport, auth) keep their variant
+// and source for callers to match on, the document only being logged
+fn document_error(e: CompletionError, index: usize, total: usize) -> CompletionError {
+    if total == 1 {
+        return e;
+    }
+
+    let document = format!("Recognition of document {} of {} failed", index + 1, total);
+    match e {
+        CompletionError::ProviderError(msg) => {
+            CompletionError::ProviderError(format!("{}: {}", document, msg))
+        }
+        CompletionError::ResponseError(msg) => {
+            CompletionError::ResponseError(format!("{}: {}", document, msg))
+        }
+        e => {
+            tracing::debug!("{}: {}", document, e);
+            e
+        }
+    }
+}
+
+// Yandex bills OCR per recognized page, so the page count is reported as
+// input units; there is nothing comparable to output tokens
+impl GetTokenUsage for CompletionResponse {
+    fn token_usage(&self) -> Option<rig::completion::Usage> {
+        let pages = self.recognized_pages()?;
+
+        let mut usage = rig::completion::Usage::new();
+        usage.input_tokens = pages;
+        usage.total_tokens = pages;
+
+        Some(usage)
+    }
+}
+
+impl TryFrom<CompletionResponse> for completion::CompletionResponse<CompletionResponse> {
+    type Error = CompletionError;
+
+    fn try_from(response: CompletionResponse) -> Result<Self, Self::Error> {
+        response.into_completion(TextOutput::default())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rig::completion::CompletionModel as _;
+
+    fn document_request(content: &str) -> CompletionRequest {
+        CompletionRequest {
+            preamble: None,
+            chat_history: OneOrMany::one(message::Message::User {
+                content: OneOrMany::one(message::UserContent::Document(message::Document {
+                    data: DocumentSourceKind::Base64(content.to_string()),
+                    media_type: Some(message::DocumentMediaType::PDF),
+                    additional_params: None,
+                })),
+            }),
+            documents: vec![],
+            tools: vec![],
+            temperature: None,
+            max_tokens: None,
+            tool_choice: None,
+            additional_params: None,
+        }
+    }
+
+    fn mock_model(server: &httpmock::MockServer) -> CompletionModel {
+        Client::builder()
+            .api_key("key")
+            .base_url(&server.base_url())
+            .build()
+            .unwrap()
+            .completion_model_typed(YandexModel::Page)
+            .poll_config(5, time::Duration::from_millis(10))
+    }
+
+    // body of a freshly started recognition operation
+    fn operation(id: &str) -> serde_json::Value {
+        serde_json::json!({
+            "id": id,
+            "description": "Recognize text",
+            "done": false
+        })
+    }
+
+    fn recognition(text: &str) -> serde_json::Value {
+        serde_json::json!({
+            "result": {
+                "textAnnotation": {
+                    "width": "1000",
+                    "height": "500",
+                    "blocks": [{
+                        "boundingBox": { "vertices": [
+                            { "x": "10", "y": "20" },
+                            { "x": "10", "y": "40" },
+                            { "x": "200", "y": "40" },
+                            { "x": "200", "y": "20" }
+                        ] },
+                        "lines": [],
+                        "languages": [{ "languageCode": "ru" }],
+                        "textSegments": [],
+                        "layoutType": "LAYOUT_TYPE_TEXT"
+                    }],
+                    "entities": [{ "name": "name", "text": "IVAN" }],
+                    "tables": [],
+                    "fullText": text
+                },
+                "page": "0"
+            }
+        })
+    }
+
+    #[tokio::test]
+    async fn async_completion_polls_until_the_result_is_ready() {
+        let server = httpmock::MockServer::start_async().await;
+        let content = BASE64_STANDARD.encode("document");
+
+        let init = server
+            .mock_async(|when, then| {
+                when.method(httpmock::Method::POST)
+                    .path("/recognizeTextAsync")
+                    .header("Authorization", "Api-Key key")
+                    .json_body(serde_json::json!({
+                        "mimeType": "application/pdf",
+                        "languageCodes": ["ru", "en"],
+                        "model": "page",
+                        "content": content
+                    }));
+                then.status(200).json_body(operation("op"));
+            })
+            .await;
+        let pending = server
+            .mock_async(|when, then| {
+                when.method(httpmock::Method::GET)
+                    .path("/getRecognition")
+                    .query_param("operationId", "op");
+                then.status(404)
+                    .json_body(serde_json::json!({ "message": "not ready" }));
+            })
+            .await;
+
+        let model = mock_model(&server)
+            .with_languages(vec!["ru".to_string(), "en".to_string()])
+            .poll_config(100, time::Duration::from_millis(10));
+        let task = tokio::spawn(async move { model.completion(document_request(&content)).await });
+
+        while pending.hits_async().await < 2 {
+            tokio::time::sleep(time::Duration::from_millis(5)).await;
+        }
+        pending.delete_async().await;
+        let done = server
+            .mock_async(|when, then| {
+                when.method(httpmock::Method::GET)
+                    .path("/getRecognition")
+                    .query_param("operationId", "op");
+                then.status(200).json_body(recognition("recognized"));
+            })
+            .await;
+
+        let response = task.await.unwrap().unwrap();
+        init.assert_async().await;
+        done.assert_async().await;
+
+        let result = response.raw_response.result_ocr();
+        assert_eq!(result.page.as_deref(), Some("0"));
+        assert_eq!(result.text_ann.width, Some(1000));
+        let block = &response.raw_response.annotation().blocks.as_ref().unwrap()[0];
+        assert_eq!(block.bounding_box.vertices[2].x, 200);
+        assert_eq!(response.raw_response.detected_languages(), vec!["ru"]);
+        assert_eq!(response.raw_response.entity("name"), Some("IVAN"));
+        assert_eq!(response.raw_response.entity("surname"), None);
+        let operations = &response.raw_response.operations;
+        assert_eq!(operations.len(), 1);
+        assert_eq!(operations[0].id, "op");
+        assert!(operations[0].done);
+        let stats = response.raw_response.stats;
+        assert!(stats.poll_attempts >= 3);
+        assert!(stats.elapsed >= time::Duration::from_millis(20));
+        assert_eq!(
+            response.raw_response.to_string(),
+            "1 page(s), 10 chars, 1 block(s), 0 table(s), 1 entities"
+        );
+        assert_eq!(
+            response.choice.first(),
+            AssistantContent::text("recognized")
+        );
+    }
+
+    #[tokio::test]
+    async fn failed_operations_report_their_description() {
+        let server = httpmock::MockServer::start_async().await;
+        server
+            .mock_async(|when, then| {
+                when.method(httpmock::Method::POST)
+                    .path("/recognizeTextAsync");
+                then.status(200).json_body(operation("op"));
+            })
+            .await;
+        server
+            .mock_async(|when, then| {
+                when.method(httpmock::Method::GET).path("/getRecognition");
+                then.status(200).json_body(serde_json::json!({
+                    "id": "op",
+                    "done": true,
+                    "error": { "code": 3, "message": "page is unreadable" }
+                }));
+            })
+            .await;
+
+        let err = mock_model(&server)
+            .completion(document_request(&BASE64_STANDARD.encode("document")))
+            .await
+            .unwrap_err();
+
+        let CompletionError::ProviderError(message) = err else {
+            panic!("unexpected error: {err}");
+        };
+        assert!(message.contains("Operation op (Recognize text)"));
+        assert!(message.contains("page is unreadable"));
+    }
+
+    #[tokio::test]
+    async fn finished_operation_without_a_result_is_a_provider_error() {
+        let server = httpmock::MockServer::start_async().await;
+        server
+            .mock_async(|when, then| {
+                when.method(httpmock::Method::POST)
+                    .path("/recognizeTextAsync");
+                then.status(200).json_body(operation("op"));
+            })
+            .await;
+        server
+            .mock_async(|when, then| {
+                when.method(httpmock::Method::GET).path("/getRecognition");
+                then.status(200).json_body(serde_json::json!({
+                    "id": "op",
+                    "description": "Recognize text",
+                    "done": true
+                }));
+            })
+            .await;
+
+        let err = mock_model(&server)
+            .completion(document_request(&BASE64_STANDARD.encode("document")))
+            .await
+            .unwrap_err();
+
+        let CompletionError::ProviderError(message) = err else {
+            panic!("unexpected error: {err}");
+        };
+        assert!(message.contains("Operation op (Recognize text)"));
+        assert!(message.contains("without a recognition result"));
+    }
+
+    #[tokio::test]
+    async fn running_operation_status_is_not_a_result() {
+        let server = httpmock::MockServer::start_async().await;
+        server
+            .mock_async(|when, then| {
+                when.method(httpmock::Method::POST)
+                    .path("/recognizeTextAsync");
+                then.status(200).json_body(operation("op"));
+            })
+            .await;
+        let running = server
+            .mock_async(|when, then| {
+                when.method(httpmock::Method::GET).path("/getRecognition");
+                then.status(200).json_body(operation("op"));
+            })
+            .await;
+
+        let model = mock_model(&server);
+        let handle = model
+            .submit(document_request(&BASE64_STANDARD.encode("document")))
+            .await
+            .unwrap();
+        assert!(model.fetch(&handle).await.unwrap().is_none());
+
+        running.delete_async().await;
+        server
+            .mock_async(|when, then| {
+                when.method(httpmock::Method::GET).path("/getRecognition");
+                then.status(200).json_body(recognition("recognized"));
+            })
+            .await;
+
+        let response = model.fetch(&handle).await.unwrap().unwrap();
+        assert_eq!(response.full_text(), "recognized");
+    }
+
+    #[tokio::test]
+    async fn recognition_paths_can_be_moved() {
+        let server = httpmock::MockServer::start_async().await;
+        let start = server
+            .mock_async(|when, then| {
+                when.method(httpmock::Method::POST).path("/gw/ocr/start");
+                then.status(200).json_body(operation("op"));
+            })
+            .await;
+        let poll = server
+            .mock_async(|when, then| {
+                when.method(httpmock::Method::GET)
+                    .path("/gw/ocr/poll")
+                    .query_param("operationId", "op");
+                then.status(200).json_body(recognition("gateway"));
+            })
+            .await;
+
+        let response = Client::builder()
+            .api_key("key")
+            .base_url(&server.base_url())
+            .async_path("/gw/ocr/start")
+            .poll_path("gw/ocr/poll")
+            .build()
+            .unwrap()
+            .completion_model_typed(YandexModel::Page)
+            .poll_config(5, time::Duration::from_millis(10))
+            .completion(document_request(&BASE64_STANDARD.encode("document")))
+            .await
+            .unwrap();
+
+        assert_eq!(response.raw_response.full_text(), "gateway");
+        start.assert_async().await;
+        poll.assert_async().await;
+
+        let recognize = server
+            .mock_async(|when, then| {
+                when.method(httpmock::Method::POST).path("/gw/ocr/sync");
+                then.status(200).json_body(recognition("sync gateway"));
+            })
+            .await;
+        let response = Client::builder()
+            .api_key("key")
+            .base_url(&server.base_url())
+            .sync_path("/gw/ocr/sync")
+            .build()
+            .unwrap()
+            .completion_model_typed(YandexModel::Page)
+            .mode(RecognitionMode::Sync)
+            .completion(document_request(&BASE64_STANDARD.encode("document")))
+            .await
+            .unwrap();
+
+        assert_eq!(response.raw_response.full_text(), "sync gateway");
+        recognize.assert_async().await;
+    }
+
+    #[tokio::test]
+    async fn resumed_operation_is_polled_without_starting_it() {
+        let server = httpmock::MockServer::start_async().await;
+        let start = server
+            .mock_async(|when, then| {
+                when.method(httpmock::Method::POST)
+                    .path("/recognizeTextAsync");
+                then.status(200).json_body(operation("other"));
+            })
+            .await;
+        let poll = server
+            .mock_async(|when, then| {
+                when.method(httpmock::Method::GET)
+                    .path("/getRecognition")
+                    .query_param("operationId", "op");
+                then.status(200).json_body(recognition("resumed"));
+            })
+            .await;
+
+        let response = mock_model(&server).resume("op").await.unwrap();
+
+        assert_eq!(response.full_text(), "resumed");
+        assert_eq!(response.operations.len(), 1);
+        assert_eq!(response.operations[0].id, "op");
+        assert!(response.operations[0].done);
+        poll.assert_hits_async(1).await;
+        start.assert_hits_async(0).await;
+    }
+
+    #[tokio::test]
+    async fn resume_reports_the_status_of_a_rejected_poll() {
+        let server = httpmock::MockServer::start_async().await;
+        let poll = server
+            .mock_async(|when, then| {
+                when.method(httpmock::Method::GET).path("/getRecognition");
+                then.status(403)
+                    .json_body(serde_json::json!({ "message": "Permission denied" }));
+            })
+            .await;
+
+        let err = mock_model(&server).resume("op").await.unwrap_err();
+
+        let CompletionError::ProviderError(message) = err else {
+            panic!("unexpected error: {err}");
+        };
+        assert!(message.contains("Operation op"));
+        assert!(message.contains("403"));
+        assert!(message.contains("Permission denied"));
+        poll.assert_hits_async(1).await;
+    }
+
+    #[test]
+    fn document_errors_keep_their_variant() {
+        let timeout = document_error(YaErr::TimeoutErr("slow".to_string()).into(), 1, 2);
+        let CompletionError::RequestError(source) = timeout else {
+            panic!("unexpected error: {timeout}");
+        };
+        assert!(matches!(
+            source.downcast_ref::<YaErr>(),
+            Some(YaErr::TimeoutErr(_))
+        ));
+
+        let provider = document_error(CompletionError::ProviderError("bad".to_string()), 1, 2);
+        assert!(matches!(
+            provider,
+            CompletionError::ProviderError(msg) if msg == "Recognition of document 2 of 2 failed: bad"
+        ));
+        let response = document_error(CompletionError::ResponseError("empty".to_string()), 0, 3);
+        assert!(matches!(response, CompletionError::ResponseError(msg) if msg.contains("1 of 3")));
+    }
+
+    #[tokio::test]
+    async fn rejected_polls_fail_instead_of_pending() {
+        let server = httpmock::MockServer::start_async().await;
+        let poll = server
+            .mock_async(|when, then| {
+                when.method(httpmock::Method::GET).path("/getRecognition");
+                then.status(401)
+                    .json_body(serde_json::json!({ "message": "Unauthenticated" }));
+            })
+            .await;
+
+        let handle = OperationHandle {
+            operation_ids: vec!["op".to_string()],
+            request_ids: Vec::new(),
+        };
+        let err = mock_model(&server).fetch(&handle).await.unwrap_err();
+
+        let CompletionError::ProviderError(message) = err else {
+            panic!("unexpected error: {err}");
+        };
+        assert!(message.contains("401"));
+        assert!(message.contains("Unauthenticated"));
+        poll.assert_hits_async(1).await;
+    }
+
+    // ids of the requests seen by `request_id_seen`
+    static REQUEST_IDS: std::sync::Mutex<Vec<String>> = std::sync::Mutex::new(Vec::new());
+
+    fn request_id_seen(req: &httpmock::prelude::HttpMockRequest) -> bool {
+        let headers = req.headers.iter().flatten();
+        let ids: Vec<_> = headers
+            .filter(|(name, _)| {
+                name.eq_ignore_ascii_case("x-client-request-id")
+                    || name.eq_ignore_ascii_case("idempotency-key")
+            })
+            .map(|(_, value)| value.clone())
+            .collect();
+        REQUEST_IDS.lock().unwrap().extend(ids);
+        true
+    }
+
+    #[tokio::test]
+    async fn retried_starts_share_the_request_id() {
+        let server = httpmock::MockServer::start_async().await;
+        let unavailable = server
+            .mock_async(|when, then| {
+                when.method(httpmock::Method::POST)
+                    .path("/recognizeTextAsync")
+                    .matches(request_id_seen);
+                then.status(503).header("Retry-After", "0");
+            })
+            .await;
+
+        let model = mock_model(&server).max_retries(2);
+        let request = document_request(&BASE64_STANDARD.encode("document"));
+        assert!(model.submit(request.clone()).await.is_err());
+        unavailable.assert_hits_async(3).await;
+
+        let retried = std::mem::take(&mut *REQUEST_IDS.lock().unwrap());
+        assert!(!retried.is_empty());
+        assert!(retried.iter().all(|id| *id == retried[0]));
+
+        unavailable.delete_async().await;
+        server
+            .mock_async(|when, then| {
+                when.method(httpmock::Method::POST)
+                    .path("/recognizeTextAsync")
+                    .matches(request_id_seen);
+                then.status(200).json_body(operation("op"));
+            })
+            .await;
+
+        let handle = model.submit(request).await.unwrap();
+        let ids = std::mem::take(&mut *REQUEST_IDS.lock().unwrap());
+        assert_eq!(handle.request_ids.len(), 1);
+        assert!(!ids.is_empty());
+        assert!(ids.iter().all(|id| *id == handle.request_ids[0]));
+        // a new recognition gets a new id
+        assert_ne!(handle.request_ids[0], retried[0]);
+    }
+
+    #[cfg(feature = "yandex-blocking")]
+    #[test]
+    fn blocking_recognition_needs_no_runtime() {
+        let server = httpmock::MockServer::start();
+        let recognize = server.mock(|when, then| {
+            when.method(httpmock::Method::POST).path("/recognizeText");
+            then.status(200).json_body(recognition("blocking"));
+        });
+
+        let response = mock_model(&server)
+            .mode(RecognitionMode::Sync)
+            .recognize_blocking(document_request(&BASE64_STANDARD.encode("document")))
+            .unwrap();
+
+        assert_eq!(response.raw_response.full_text(), "blocking");
+        recognize.assert();
+    }
+
+    #[cfg(feature = "yandex-blocking")]
+    #[tokio::test]
+    async fn blocking_recognition_fails_in_async_code() {
+        let server = httpmock::MockServer::start_async().await;
+        let err = mock_model(&server)
+            .recognize_blocking(document_request(&BASE64_STANDARD.encode("document")))
+            .unwrap_err();
+
+        assert!(err.to_string().contains("async code"));
+    }
+
+    #[tokio::test]
+    async fn no_auth_sends_no_credentials() {
+        let server = httpmock::MockServer::start_async().await;
+        let recognize = server
+            .mock_async(|when, then| {
+                when.method(httpmock::Method::POST)
+                    .path("/recognizeText")
+                    .matches(|req| {
+                        req.headers
+                            .iter()
+                            .flatten()
+                            .all(|(name, _)| !name.eq_ignore_ascii_case("authorization"))
+                    });
+                then.status(200).json_body(recognition("recognized"));
+            })
+            .await;
+
+        assert!(Client::builder().build().is_err());
+        let model = Client::builder()
+            .no_auth()
+            .base_url(&server.base_url())
+            .build()
+            .unwrap()
+            .completion_model_typed(YandexModel::Page)
+            .mode(RecognitionMode::Sync);
+        model
+            .completion(document_request(&BASE64_STANDARD.encode("document")))
+            .await
+            .unwrap();
+
+        recognize.assert_async().await;
+    }
+
+    #[tokio::test]
+    async fn model_base_url_overrides_the_client_one() {
+        let server = httpmock::MockServer::start_async().await;
+        let recognize = server
+            .mock_async(|when, then| {
+                when.method(httpmock::Method::POST)
+                    .path("/v2/recognizeText");
+                then.status(200).json_body(recognition("recognized"));
+            })
+            .await;
+
+        let model = mock_model(&server)
+            .base_url(&format!("{}/v2/", server.base_url()))
+            .mode(RecognitionMode::Sync);
+        model
+            .completion(document_request(&BASE64_STANDARD.encode("document")))
+            .await
+            .unwrap();
+
+        recognize.assert_async().await;
+    }
+
+    #[tokio::test]
+    async fn files_are_recognized_with_the_inferred_media_type() {
+        let server = httpmock::MockServer::start_async().await;
+        let recognize = server
+            .mock_async(|when, then| {
+                when.method(httpmock::Method::POST)
+                    .path("/recognizeText")
+                    .json_body_partial(r#"{ "mimeType": "image/png" }"#);
+                then.status(200).json_body(recognition("recognized"));
+            })
+            .await;
+
+        let dir = assert_fs::TempDir::new().unwrap();
+        let path = dir.path().join("scan.png");
+        std::fs::write(&path, b"png").unwrap();
+
+        let model = mock_model(&server).mode(RecognitionMode::Sync);
+        let response = model.recognize_file(&path).await.unwrap();
+        recognize.assert_async().await;
+        assert_eq!(response.full_text(), "recognized");
+
+        let gif = dir.path().join("scan.gif");
+        std::fs::write(&gif, b"gif").unwrap();
+        assert!(model.recognize_file(&gif).await.is_err());
+    }
+
+    // PDF of `pages` empty pages
+    #[cfg(feature = "pdf")]
+    fn pdf(pages: usize) -> Vec<u8> {
+        use lopdf::{Document, Object, dictionary};
+
+        let mut document = Document::with_version("1.5");
+        let pages_id = document.new_object_id();
+        let kids: Vec<Object> = (0..pages)
+            .map(|_| {
+                document
+                    .add_object(dictionary! {
+                        "Type" => "Page",
+                        "Parent" => pages_id,
+                        "MediaBox" => vec![0.into(), 0.into(), 595.into(), 842.into()],
+                    })
+                    .into()
+            })
+            .collect();
+        document.objects.insert(
+            pages_id,
+            Object::Dictionary(dictionary! {
+                "Type" => "Pages",
+                "Kids" => kids,
+                "Count" => pages as i64,
+            }),
+        );
+        let catalog_id = document.add_object(dictionary! {
+            "Type" => "Catalog",
+            "Pages" => pages_id,
+        });
+        document.trailer.set("Root", catalog_id);
+
+        let mut out = Vec::new();
+        document.save_to(&mut out).unwrap();
+        out
+    }
+
+    #[cfg(feature = "pdf")]
+    #[test]
+    fn long_pdfs_are_split_into_chunks() {
+        let chunks = split_pdf(&pdf(5), 2).unwrap();
+        let pages: Vec<usize> = chunks
+            .iter()
+            .map(|c| lopdf::Document::load_mem(c).unwrap().get_pages().len())
+            .collect();
+        assert_eq!(pages, [2, 2, 1]);
+
+        assert_eq!(split_pdf(&pdf(2), 2).unwrap().len(), 1);
+    }
+
+    #[cfg(feature = "pdf")]
+    #[tokio::test]
+    async fn pdf_chunks_are_merged_in_page_order() {
+        let server = httpmock::MockServer::start_async().await;
+        let init = server
+            .mock_async(|when, then| {
+                when.method(httpmock::Method::POST)
+                    .path("/recognizeTextAsync");
+                then.status(200).json_body(operation("op"));
+            })
+            .await;
+        server
+            .mock_async(|when, then| {
+                when.method(httpmock::Method::GET).path("/getRecognition");
+                then.status(200).json_body(recognition("chunk"));
+            })
+            .await;
+
+        let response = mock_model(&server)
+            .pdf_chunk_pages(2)
+            .completion(document_request(&BASE64_STANDARD.encode(pdf(3))))
+            .await
+            .unwrap();
+
+        init.assert_hits_async(2).await;
+        let pages: Vec<_> = response
+            .raw_response
+            .pages()
+            .iter()
+            .map(|p| p.page.as_deref().unwrap())
+            .collect();
+        assert_eq!(pages, ["0", "2"]);
+    }
+
+    #[tokio::test]
+    async fn batch_results_are_streamed_in_order() {
+        let server = httpmock::MockServer::start_async().await;
+        for (content, text) in [("first", "one"), ("second", "two")] {
+            let body = serde_json::json!({ "content": BASE64_STANDARD.encode(content) });
+            server
+                .mock_async(|when, then| {
+                    when.method(httpmock::Method::POST)
+                        .path("/recognizeText")
+                        .json_body_partial(body.to_string());
+                    then.status(200).json_body(recognition(text));
+                })
+                .await;
+        }
+
+        let docs = ["first", "second"].map(|content| message::Document {
+            data: DocumentSourceKind::Base64(BASE64_STANDARD.encode(content)),
+            media_type: Some(message::DocumentMediaType::PDF),
+            additional_params: None,
+        });
+        let texts: Vec<String> = mock_model(&server)
+            .mode(RecognitionMode::Sync)
+            .recognize_stream(docs)
+            .map(|response| response.unwrap().full_text().to_string())
+            .collect()
+            .await;
+
+        assert_eq!(texts, ["one", "two"]);
+    }
+
+    #[tokio::test]
+    async fn markdown_is_requested_and_returned() {
+        let server = httpmock::MockServer::start_async().await;
+        let mut body = recognition("Title");
+        body["result"]["textAnnotation"]["markdown"] = "# Title".into();
+        let recognize = server
+            .mock_async(|when, then| {
+                when.method(httpmock::Method::POST)
+                    .path("/recognizeText")
+                    .json_body_partial(r#"{ "model": "markdown" }"#)
+                    // markdown is generated unless it is turned off
+                    .matches(|req| {
+                        !String::from_utf8_lossy(req.body.as_deref().unwrap_or_default())
+                            .contains("\"markdown\":false")
+                    });
+                then.status(200).json_body(body);
+            })
+            .await;
+        let doc = || message::Document {
+            data: DocumentSourceKind::Base64(BASE64_STANDARD.encode("scan")),
+            media_type: Some(message::DocumentMediaType::PDF),
+            additional_params: None,
+        };
+
+        let client = Client::builder()
+            .api_key("key")
+            .base_url(&server.base_url())
+            .build()
+            .unwrap();
+        let markdown = client
+            .completion_model_typed(YandexModel::Markdown)
+            .mode(RecognitionMode::Sync)
+            .artifacts(OutputArtifacts {
+                markdown: false,
+                ..Default::default()
+            })
+            .recognize_to_markdown(doc())
+            .await
+            .unwrap();
+
+        assert_eq!(markdown, "# Title");
+        recognize.assert_async().await;
+
+        recognize.delete_async().await;
+        server
+            .mock_async(|when, then| {
+                when.method(httpmock::Method::POST).path("/recognizeText");
+                then.status(200).json_body(recognition("Title"));
+            })
+            .await;
+        let err = mock_model(&server)
+            .mode(RecognitionMode::Sync)
+            .recognize_to_markdown(doc())
+            .await
+            .unwrap_err();
+        assert!(matches!(err, CompletionError::ResponseError(msg) if msg.contains("Markdown")));
+
+        // waits for a free slot like the other recognitions
+        let model = client
+            .max_concurrent(1)
+            .completion_model_typed(YandexModel::Markdown)
+            .mode(RecognitionMode::Sync);
+        let _busy = model.client.acquire_permit().await.unwrap();
+        let pending = tokio::time::timeout(
+            time::Duration::from_millis(50),
+            model.recognize_to_markdown(doc()),
+        )
+        .await;
+        assert!(pending.is_err());
+    }
+
+    #[test]
+    fn media_type_is_sniffed_from_magic_bytes() {
+        let sniff = |bytes: &[u8]| sniff_mime(&BASE64_STANDARD.encode(bytes));
+        assert_eq!(sniff(b"\xFF\xD8\xFF\xE0 jfif"), Some("image/jpeg"));
+        assert_eq!(sniff(b"\x89PNG\r\n\x1a\n"), Some("image/png"));
+        assert_eq!(sniff(b"%PDF-1.7"), Some("application/pdf"));
+        assert_eq!(sniff(b"GIF89a"), None);
+        assert_eq!(sniff_mime("not base64!"), None);
+    }
+
+    #[test]
+    fn oversized_content_is_rejected() {
+        let content = BASE64_STANDARD.encode([0u8; 10]);
+        assert_eq!(decoded_len(&content), 10);
+        assert!(check_content_size(&content, 10).is_ok());
+
+        let err = check_content_size(&content, 9).unwrap_err();
+        assert!(
+            matches!(&err, YaErr::ReqErr(msg) if msg.contains("10 bytes") && msg.contains("9 bytes"))
+        );
+    }
+
+    #[tokio::test]
+    async fn undeclared_media_type_is_sniffed() {
+        let server = httpmock::MockServer::start_async().await;
+        let recognize = server
+            .mock_async(|when, then| {
+                when.method(httpmock::Method::POST)
+                    .path("/recognizeText")
+                    .json_body_partial(r#"{ "mimeType": "application/pdf" }"#);
+                then.status(200).json_body(recognition("sniffed"));
+            })
+            .await;
+
+        let request = |content: &[u8]| {
+            let mut request = document_request(&BASE64_STANDARD.encode(content));
+            request.chat_history = OneOrMany::one(message::Message::User {
+                content: OneOrMany::one(message::UserContent::Document(message::Document {
+                    data: DocumentSourceKind::Base64(BASE64_STANDARD.encode(content)),
+                    media_type: None,
+                    additional_params: None,
+                })),
+            });
+            request
+        };
+
+        let model = mock_model(&server).mode(RecognitionMode::Sync);
+        let response = model.completion(request(b"%PDF-1.7 body")).await.unwrap();
+        recognize.assert_async().await;
+        assert_eq!(response.raw_response.full_text(), "sniffed");
+
+        let err = model.completion(request(b"plain text")).await.unwrap_err();
+        assert!(err.to_string().contains("could not be detected"));
+    }
+
+    #[tokio::test]
+    async fn sync_completion_uses_a_single_request() {
+        let server = httpmock::MockServer::start_async().await;
+        let content = BASE64_STANDARD.encode("document");
+
+        let recognize = server
+            .mock_async(|when, then| {
+                when.method(httpmock::Method::POST)
+                    .path("/recognizeText")
+                    .json_body_partial(r#"{ "model": "page", "languageCodes": ["ru"] }"#);
+                then.status(200).json_body(recognition("recognized"));
+            })
+            .await;
+
+        let response = mock_model(&server)
+            .mode(RecognitionMode::Sync)
+            .completion(document_request(&content))
+            .await
+            .unwrap();
+
+        recognize.assert_async().await;
+        assert_eq!(response.raw_response.full_text(), "recognized");
+        assert_eq!(response.raw_response.entity("name"), Some("IVAN"));
+    }
+
+    #[tokio::test]
+    async fn concurrent_documents_keep_their_order() {
+        let server = httpmock::MockServer::start_async().await;
+        for (content, text, delay) in [("first", "slow", 200), ("second", "fast", 0)] {
+            let body = serde_json::json!({ "content": BASE64_STANDARD.encode(content) });
+            server
+                .mock_async(|when, then| {
+                    when.method(httpmock::Method::POST)
+                        .path("/recognizeText")
+                        .json_body_partial(body.to_string());
+                    then.status(200)
+                        .delay(time::Duration::from_millis(delay))
+                        .json_body(recognition(text));
+                })
+                .await;
+        }
+
+        let mut request = document_request(&BASE64_STANDARD.encode("first"));
+        request.chat_history = OneOrMany::one(message::Message::User {
+            content: OneOrMany::many(["first", "second"].map(|content| {
+                message::UserContent::Document(message::Document {
+                    data: DocumentSourceKind::Base64(BASE64_STANDARD.encode(content)),
+                    media_type: Some(message::DocumentMediaType::PDF),
+                    additional_params: None,
+                })
+            }))
+            .unwrap(),
+        });
+
+        let client = Client::builder()
+            .api_key("key")
+            .base_url(&server.base_url())
+            .max_concurrent(2)
+            .build()
+            .unwrap();
+        let response = client
+            .completion_model_typed(YandexModel::Page)
+            .mode(RecognitionMode::Sync)
+            .completion(request)
+            .await
+            .unwrap();
+
+        let texts: Vec<_> = response
+            .raw_response
+            .pages()
+            .iter()
+            .map(|page| page.text_ann.full_text.as_str())
+            .collect();
+        assert_eq!(texts, ["slow", "fast"]);
+    }
+
+    #[tokio::test]
+    async fn max_concurrent_is_shared_by_the_completions_of_a_client() {
+        let server = httpmock::MockServer::start_async().await;
+        server
+            .mock_async(|when, then| {
+                when.method(httpmock::Method::POST).path("/recognizeText");
+                then.status(200)
+                    .delay(time::Duration::from_millis(200))
+                    .json_body(recognition("recognized"));
+            })
+            .await;
+
+        let model = Client::builder()
+            .api_key("key")
+            .base_url(&server.base_url())
+            .max_concurrent(1)
+            .build()
+            .unwrap()
+            .completion_model_typed(YandexModel::Page)
+            .mode(RecognitionMode::Sync);
+        // a clone shares the cap with the original
+        let other = model.clone();
+        let started = time::Instant::now();
+        let (first, second) = tokio::join!(
+            model.completion(document_request(&BASE64_STANDARD.encode("first"))),
+            other.completion(document_request(&BASE64_STANDARD.encode("second")))
+        );
+
+        first.unwrap();
+        second.unwrap();
+        // one after the other, not side by side
+        assert!(started.elapsed() >= time::Duration::from_millis(400));
+    }
+
+    #[test]
+    fn max_concurrent_is_clamped_to_the_semaphore_limit() {
+        let client = Client::builder()
+            .api_key("key")
+            .max_concurrent(usize::MAX)
+            .build()
+            .unwrap();
+        assert_eq!(client.max_concurrent, Semaphore::MAX_PERMITS);
+
+        let client = client.max_concurrent(usize::MAX);
+        assert_eq!(client.permits.available_permits(), Semaphore::MAX_PERMITS);
+    }
+
+    #[tokio::test]
+    async fn disabled_artifacts_are_requested_off_and_dropped() {
+        let server = httpmock::MockServer::start_async().await;
+        let recognize = server
+            .mock_async(|when, then| {
+                when.method(httpmock::Method::POST)
+                    .path("/recognizeText")
+                    .json_body_partial(r#"{ "entities": false }"#);
+                then.status(200).json_body(recognition("recognized"));
+            })
+            .await;
+
+        let response = mock_model(&server)
+            .mode(RecognitionMode::Sync)
+            .artifacts(OutputArtifacts {
+                entities: false,
+                ..Default::default()
+            })
+            .completion(document_request(&BASE64_STANDARD.encode("document")))
+            .await
+            .unwrap();
+
+        recognize.assert_async().await;
+        assert!(response.raw_response.entities().is_empty());
+        assert_eq!(response.raw_response.entity("name"), None);
+    }
+
+    #[cfg(feature = "yandex-gzip")]
+    fn gzipped_request(req: &httpmock::prelude::HttpMockRequest) -> bool {
+        use std::io::Read;
+
+        let body = req.body.as_deref().unwrap_or_default();
+        let mut json = String::new();
+        flate2::read::GzDecoder::new(body)
+            .read_to_string(&mut json)
+            .is_ok_and(|_| json.contains(r#""mimeType":"application/pdf""#))
+    }
+
+    #[cfg(feature = "yandex-gzip")]
+    #[tokio::test]
+    async fn large_requests_are_gzipped() {
+        let server = httpmock::MockServer::start_async().await;
+        let gzipped = server
+            .mock_async(|when, then| {
+                when.method(httpmock::Method::POST)
+                    .path("/recognizeText")
+                    .header("content-encoding", "gzip")
+                    .matches(gzipped_request);
+                then.status(200).json_body(recognition("gzipped"));
+            })
+            .await;
+
+        let model = mock_model(&server).mode(RecognitionMode::Sync);
+        let request = document_request(&BASE64_STANDARD.encode("document"));
+        let response = model
+            .clone()
+            .gzip_requests(64)
+            .completion(request.clone())
+            .await
+            .unwrap();
+        assert_eq!(response.raw_response.full_text(), "gzipped");
+        gzipped.assert_async().await;
+
+        // below the threshold the body is sent as is
+        gzipped.delete_async().await;
+        let plain = server
+            .mock_async(|when, then| {
+                when.method(httpmock::Method::POST)
+                    .path("/recognizeText")
+                    .json_body_partial(r#"{ "model": "page" }"#);
+                then.status(200).json_body(recognition("plain"));
+            })
+            .await;
+        model
+            .gzip_requests(1 << 20)
+            .completion(request)
+            .await
+            .unwrap();
+        plain.assert_async().await;
+    }
+
+    #[tokio::test]
+    async fn requests_are_built_without_sending() {
+        let server = httpmock::MockServer::start_async().await;
+        let any = server
+            .mock_async(|_, then| {
+                then.status(200);
+            })
+            .await;
+
+        let mut request = document_request(&BASE64_STANDARD.encode("document"));
+        request.additional_params = Some(serde_json::json!({ "languageCodes": ["kk"] }));
+        let requests = mock_model(&server).build_request(&request).await.unwrap();
+
+        assert_eq!(requests.len(), 1);
+        assert_eq!(requests[0].mime_type, "application/pdf");
+        assert_eq!(requests[0].language_codes, vec!["kk"]);
+        assert_eq!(requests[0].model, "page");
+        assert_eq!(requests[0].content, BASE64_STANDARD.encode("document"));
+        any.assert_hits_async(0).await;
+    }
+
+    #[tokio::test]
+    async fn additional_params_are_merged_into_the_request() {
+        let server = httpmock::MockServer::start_async().await;
+        let recognize = server
+            .mock_async(|when, then| {
+                when.method(httpmock::Method::POST)
+                    .path("/recognizeText")
+                    .json_body_partial(
+                        r#"{ "model": "page", "languageCodes": ["en"], "experimental": { "flag": true } }"#,
+                    );
+                then.status(200).json_body(recognition("recognized"));
+            })
+            .await;
+
+        let mut request = document_request(&BASE64_STANDARD.encode("document"));
+        request.additional_params = Some(serde_json::json!({
+            "languageCodes": ["en"],
+            "experimental": { "flag": true },
+            "model": "handwritten"
+        }));
+        mock_model(&server)
+            .mode(RecognitionMode::Sync)
+            .completion(request.clone())
+            .await
+            .unwrap();
+        recognize.assert_async().await;
+
+        request.additional_params = Some(serde_json::json!(["not", "an", "object"]));
+        let err = mock_model(&server).completion(request).await.unwrap_err();
+        assert!(err.to_string().contains("JSON object"));
+    }
+
+    #[tokio::test]
+    async fn quality_hints_are_sent_when_set() {
+        let server = httpmock::MockServer::start_async().await;
+        let recognize = server
+            .mock_async(|when, then| {
+                when.method(httpmock::Method::POST)
+                    .path("/recognizeText")
+                    .json_body_partial(r#"{ "dpi": 150 }"#);
+                then.status(200).json_body(recognition("recognized"));
+            })
+            .await;
+
+        let model = mock_model(&server).mode(RecognitionMode::Sync);
+        let request = document_request(&BASE64_STANDARD.encode("document"));
+        model
+            .clone()
+            .quality(QualityHints { dpi: Some(150) })
+            .completion(request.clone())
+            .await
+            .unwrap();
+        recognize.assert_async().await;
+
+        let err = model
+            .quality(QualityHints { dpi: Some(10) })
+            .completion(request)
+            .await
+            .unwrap_err();
+        assert!(err.to_string().contains("DPI hint"));
+        recognize.assert_hits_async(1).await;
+    }
+
+    #[test]
+    fn completion_requests_round_trip() {
+        let request = YaCompletionRequest::default();
+        let value = serde_json::to_value(&request).unwrap();
+        let parsed: YaCompletionRequest = serde_json::from_value(value).unwrap();
+        assert_eq!(parsed.artifacts, OutputArtifacts::default());
+        assert_eq!(parsed.quality, QualityHints::default());
+
+        let request = YaCompletionRequest {
+            artifacts: OutputArtifacts {
+                markdown: false,
+                ..Default::default()
             },
-            rx: Regex::new(a_tkn_pattern.unwrap_or(YA_TOKEN_PATTERN)).unwrap(),
-            auth_t: auth_t.clone(),
-            http_client: http_client,
-            langs: a_langs.unwrap_or(vec!["ru".to_string()]),
+            ..Default::default()
         };
+        let value = serde_json::to_value(&request).unwrap();
+        let parsed: YaCompletionRequest = serde_json::from_value(value).unwrap();
+        assert_eq!(parsed.artifacts, request.artifacts);
+    }
 
-        if out.auth_t == AuthType::Token {
-            out.upd_token()?;
-        }
+    #[test]
+    fn requests_without_attachments_fail_before_sending() {
+        let server = httpmock::MockServer::start();
+        let model = mock_model(&server);
 
-        tracing::trace!("Created Ocr with params: {:?}", out);
+        let mut request = document_request("document");
+        request.chat_history = OneOrMany::one(message::Message::user("no document"));
+        let err = model.request_contents(&request).unwrap_err();
+        assert!(err.to_string().contains("has a document or image attached"));
 
-        return Ok(out);
+        request.chat_history = OneOrMany::one(message::Message::assistant("reply"));
+        let err = model.request_contents(&request).unwrap_err();
+        assert!(err.to_string().contains("user message"));
     }
 
-    pub fn from_fldr(a_fldr: &str) -> Self {
-        return Self::from_full(None, None, None, Some(a_fldr.to_string()), None, None, None)
-            .expect("Could not build Yandex client");
+    #[tokio::test]
+    async fn empty_chat_history_is_reported() {
+        let server = httpmock::MockServer::start_async().await;
+        let any = server
+            .mock_async(|_, then| {
+                then.status(200);
+            })
+            .await;
+
+        let mut request = document_request("document");
+        request.chat_history = OneOrMany::one(message::Message::user(""));
+        let err = mock_model(&server).completion(request).await.unwrap_err();
+
+        assert!(err.to_string().contains("Empty chat history"));
+        any.assert_hits_async(0).await;
     }
 
-    pub fn from_api(a_api: &str) -> Self {
-        return Self::from_full(None, Some(a_api.to_string()), None, None, None, None, None)
-            .expect("Could not build Yandex client");
+    #[test]
+    fn document_is_found_after_preceding_messages() {
+        let server = httpmock::MockServer::start();
+        let model = mock_model(&server);
+
+        let mut request = document_request("document");
+        let document = request.chat_history.first();
+        request.preamble = Some("Recognize the scans".to_string());
+        request.chat_history = OneOrMany::many(vec![
+            message::Message::user("Here is the scan"),
+            message::Message::assistant("Send it over"),
+            document,
+            message::Message::user("Thanks"),
+        ])
+        .unwrap();
+
+        let contents = model.request_contents(&request).unwrap();
+        assert_eq!(contents.len(), 1);
+        assert!(matches!(&contents[0].0, DocumentSourceKind::Base64(c) if c == "document"));
     }
 
-    pub fn new(api_key: &str) -> Self {
-        Self::from_full(
-            None,
-            Some(api_key.to_string()),
-            None,
-            None,
-            None,
-            None,
-            None,
-        )
-        .expect("Could not create Yandex OCR")
+    #[tokio::test]
+    async fn provider_errors_are_surfaced() {
+        let server = httpmock::MockServer::start_async().await;
+        server
+            .mock_async(|when, then| {
+                when.method(httpmock::Method::POST)
+                    .path("/recognizeTextAsync");
+                then.status(400)
+                    .json_body(serde_json::json!({ "message": "bad image" }));
+            })
+            .await;
+
+        let err = mock_model(&server)
+            .completion(document_request(&BASE64_STANDARD.encode("document")))
+            .await
+            .unwrap_err();
+
+        assert!(matches!(err, CompletionError::ProviderError(m) if m.contains("bad image")));
     }
 
-    pub fn base_url(mut self, base_url: &str) -> Self {
-        self.base_url = base_url.to_string();
-        self
+    #[test]
+    fn nested_and_flat_error_envelopes_are_parsed() {
+        assert_eq!(
+            error_message(r#"{"code": 3, "message": "invalid mime", "details": []}"#),
+            "invalid mime (3)"
+        );
+        assert_eq!(
+            error_message(
+                r#"{"error": {"code": 13, "message": "", "details": [{"reason": "internal"}]}}"#
+            ),
+            r#"{"reason":"internal"} (13)"#
+        );
+        assert_eq!(error_message("gateway timeout"), "gateway timeout");
     }
 
-    pub fn custom_client(mut self, client: reqwest::Client) -> Self {
-        self.http_client = client;
-        self
+    #[tokio::test]
+    async fn provider_errors_carry_the_status_and_error_code() {
+        let server = httpmock::MockServer::start_async().await;
+        server
+            .mock_async(|when, then| {
+                when.method(httpmock::Method::POST)
+                    .path("/recognizeTextAsync");
+                then.status(401).json_body(serde_json::json!({
+                    "error": { "code": "UNAUTHENTICATED", "message": "unknown api key" }
+                }));
+            })
+            .await;
+
+        let err = mock_model(&server)
+            .completion(document_request(&BASE64_STANDARD.encode("document")))
+            .await
+            .unwrap_err();
+
+        let CompletionError::ProviderError(message) = err else {
+            panic!("unexpected error: {err}");
+        };
+        assert!(message.contains("401"));
+        assert!(message.contains("unknown api key (UNAUTHENTICATED)"));
     }
 
-    //================================================//
-    // Token upd                                      //
-    //================================================//
-    fn upd_token(&mut self) -> Result<(), YaErr> {
-        let now: NaiveDateTime = Local::now().naive_local();
-        if self.token_upd.is_some() && self.token.is_some() {
-            let delta: TimeDelta = now - self.token_upd.unwrap();
-            if delta < YA_OCR_TOKEN_UPD {
-                tracing::debug!(
-                    "YaOcr::upd_token: not required to upd, last token updated {:?}",
-                    self.token_upd.unwrap()
-                );
-                return Ok(());
-            }
+    #[test]
+    fn client_is_send_and_sync() {
+        fn assert_send_sync<T: Send + Sync>() {}
+        assert_send_sync::<Client>();
+        assert_send_sync::<CompletionModel>();
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn shared_model_serves_concurrent_completions() {
+        let server = httpmock::MockServer::start_async().await;
+        for i in 0..8 {
+            let content = BASE64_STANDARD.encode(format!("document {}", i));
+            server
+                .mock_async(|when, then| {
+                    when.method(httpmock::Method::POST)
+                        .path("/recognizeTextAsync")
+                        .body_contains(&content);
+                    then.status(200).json_body(operation(&format!("op-{}", i)));
+                })
+                .await;
+            server
+                .mock_async(|when, then| {
+                    when.method(httpmock::Method::GET)
+                        .path("/getRecognition")
+                        .query_param("operationId", format!("op-{}", i));
+                    then.status(200).json_body(serde_json::json!({
+                        "result": { "textAnnotation": { "fullText": format!("text {}", i) } }
+                    }));
+                })
+                .await;
         }
 
-        let output = Command::new("bash")
-            .arg("-c")
-            .arg("yc iam create-token")
-            .output();
+        let model = Arc::new(mock_model(&server));
+        let tasks = (0..8)
+            .map(|i| {
+                let model = model.clone();
+                tokio::spawn(async move {
+                    let content = BASE64_STANDARD.encode(format!("document {}", i));
+                    model.completion(document_request(&content)).await
+                })
+            })
+            .collect::<Vec<_>>();
 
-        if output.is_err() {
-            return Err(YaErr::TokenUpdErr("Error on bash script".to_string()));
+        for (i, task) in tasks.into_iter().enumerate() {
+            let response = task.await.unwrap().unwrap();
+            assert_eq!(response.raw_response.full_text(), format!("text {}", i));
         }
+    }
 
-        let mut tkn = match String::from_utf8(output.unwrap().stdout) {
-            Ok(t) => t,
-            Err(e) => {
-                return Err(YaErr::TokenUpdErr(format!("Error on stdout read {}", e)));
-            }
-        };
+    #[test]
+    fn multi_page_results_are_split_into_pages() {
+        let body = r#"{"result":{"textAnnotation":{"fullText":"first"},"page":"0"}}
+{"result":{"textAnnotation":{"fullText":"second"},"page":"1"}}
+"#;
+        let pages = parse_pages(body).unwrap();
+        assert_eq!(pages.len(), 2);
 
-        tkn.pop();
+        let response = CompletionResponse::from_results(pages).unwrap();
+        assert_eq!(response.full_text(), "first");
+        assert_eq!(response.pages()[1].text_ann.full_text, "second");
+        assert_eq!(response.token_usage().unwrap().input_tokens, 2);
 
-        if !self.rx.is_match(tkn.as_str()) {
-            return Err(YaErr::TokenUpdErr(format!("Not valid token: {}", tkn)));
-        }
+        assert!(parse_pages("").is_err());
+        assert!(parse_pages(r#"{"message":"bad image"}"#).is_err());
+    }
 
-        tracing::debug!("Token has been upgraded {}", tkn.clone());
-        self.token = Some(tkn);
-        self.token_upd = Some(now);
+    #[test]
+    fn empty_text_output_keeps_only_the_raw_response() {
+        let page = serde_json::from_value::<CompletionResponse>(recognition("scan"))
+            .unwrap()
+            .result;
+        let completion = CompletionResponse::from_results(vec![page.clone(), page])
+            .unwrap()
+            .into_completion(TextOutput::Empty)
+            .unwrap();
 
-        return Ok(());
+        assert_eq!(completion.choice.len(), 1);
+        assert!(
+            matches!(completion.choice.first(), AssistantContent::Text(t) if t.text.is_empty())
+        );
+        assert_eq!(completion.raw_response.pages().len(), 2);
     }
-}
 
-impl std::fmt::Debug for Client {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        f.debug_struct("Client")
-            .field("base_url", &self.base_url)
-            .field("http_client", &self.http_client)
-            .field("api_key", &"<REDACTED>")
-            .finish()
+    #[test]
+    fn page_size_and_rotation_are_parsed() {
+        let mut body = recognition("scan");
+        body["result"]["textAnnotation"]["rotate"] = "0".into();
+        let response = serde_json::from_value::<CompletionResponse>(body).unwrap();
+        assert_eq!(response.page_size(), Some((1000, 500)));
+        assert_eq!(response.page_rotation(), Some(0));
+
+        let mut body = recognition("scan");
+        body["result"]["textAnnotation"]
+            .as_object_mut()
+            .unwrap()
+            .remove("height");
+        let response = serde_json::from_value::<CompletionResponse>(body).unwrap();
+        assert_eq!(response.page_size(), None);
+        assert_eq!(response.page_rotation(), None);
     }
-}
 
-impl Client {
-    //-----------------------------------------------//
-    // get, post utilities                           //
-    //-----------------------------------------------//
-    pub fn post(&mut self, path: &str) -> reqwest::RequestBuilder {
-        let url = format!("{}/{}", self.base_url, path).replace("//", "/");
+    #[test]
+    fn non_ascii_text_is_returned_unescaped() {
+        let mut body = recognition("Пётр 東京");
+        body["result"]["textAnnotation"]["markdown"] = "# Пётр\n東京".into();
+        body["result"]["textAnnotation"]["entities"] =
+            serde_json::json!([{ "name": "city", "text": "東京" }]);
+        // Yandex may send the text as \u escapes
+        let body = body
+            .to_string()
+            .replace("Пётр", "\\u041f\\u0451\\u0442\\u0440");
 
-        match self.auth_t {
-            AuthType::Token => {
-                self.upd_token().expect("Could not renew token");
-
-                self.http_client
-                    .post(url)
-                    .header("x-folder-id", self.folder.clone().unwrap())
-                    .header("x-data-logging-enabled", "true")
-                    .bearer_auth(self.token.clone().unwrap())
-            }
-            AuthType::ApiKey => self
-                .http_client
-                .post(url)
-                .header("x-data-logging-enabled", "true")
-                .header(
-                    "Authorization",
-                    format!("Api-Key {}", self.api_key.clone().unwrap()),
-                ),
-            AuthType::None => {
-                panic!("Auth type for yaOcr is not defined");
-            }
-        }
+        let response = CompletionResponse::from_results(parse_pages(&body).unwrap()).unwrap();
+        assert_eq!(response.full_text(), "Пётр 東京");
+        assert_eq!(response.markdown(), Some("# Пётр\n東京"));
+        assert_eq!(response.entity("city"), Some("東京"));
+
+        let json = response.to_json().unwrap();
+        assert!(json.contains("Пётр 東京"));
+        assert_eq!(
+            serde_json::from_str::<CompletionResponse>(&json).unwrap(),
+            response
+        );
+
+        let completion = response.into_completion(TextOutput::Concatenated).unwrap();
+        let AssistantContent::Text(text) = completion.choice.first() else {
+            panic!("expected a text choice");
+        };
+        assert_eq!(
+            text.text,
+            "ENTITIES:[{\"name\":\"city\",\"text\":\"東京\"}]\n\nMARKDOWN:# Пётр\n東京\n\nFULL_TEXT:Пётр 東京"
+        );
     }
 
-    pub fn get(&mut self, path: &str) -> reqwest::RequestBuilder {
-        let url = format!("{}/{}", self.base_url, path).replace("//", "/");
+    #[test]
+    fn archived_json_restores_the_response() {
+        let page = serde_json::from_value::<CompletionResponse>(recognition("archived"))
+            .unwrap()
+            .result;
+        let response = CompletionResponse::from_results(vec![page.clone(), page]).unwrap();
 
-        match self.auth_t {
-            AuthType::Token => {
-                self.upd_token().expect("Could not renew token");
-
-                self.http_client
-                    .get(url)
-                    .header("x-folder-id", self.folder.clone().unwrap())
-                    .header("x-data-logging-enabled", "true")
-                    .bearer_auth(self.token.clone().unwrap())
-            }
-            AuthType::ApiKey => self
-                .http_client
-                .get(url)
-                .header("x-data-logging-enabled", "true")
-                .header(
-                    "Authorization",
-                    format!("Api-Key {}", self.api_key.clone().unwrap()),
-                ),
-            AuthType::None => {
-                panic!("Auth type for yaOcr is not defined");
-            }
+        for json in [
+            response.to_json().unwrap(),
+            response.to_json_pretty().unwrap(),
+        ] {
+            let restored: CompletionResponse = serde_json::from_str(&json).unwrap();
+            assert_eq!(restored, response);
+            assert_eq!(restored.pages().len(), 2);
+            assert_eq!(restored.full_text(), "archived");
+            assert_eq!(restored.entity("name"), Some("IVAN"));
         }
     }
-}
 
-impl ProviderClient for Client {
-    // If you prefer the environment variable approach:
-    fn from_env() -> Self {
-        let api_key = std::env::var("YANDEX_API_KEY").expect("YANDEX_API_KEY not set");
-        Self::new(&api_key)
+    #[test]
+    fn detected_languages_are_ordered_by_frequency() {
+        let block = |codes: &[&str]| Block {
+            languages: codes
+                .iter()
+                .map(|c| Language {
+                    language_code: c.to_string(),
+                })
+                .collect(),
+            ..Default::default()
+        };
+        let page = |blocks| ResultOcr {
+            text_ann: Annotation {
+                blocks: Some(blocks),
+                ..Default::default()
+            },
+            page: None,
+        };
+
+        let response = CompletionResponse::from_results(vec![
+            page(vec![block(&["en"]), block(&["ru", "en"])]),
+            page(vec![block(&["ru"]), block(&["ru", "*"])]),
+        ])
+        .unwrap();
+
+        assert_eq!(response.detected_languages(), vec!["ru", "en"]);
     }
 
-    fn from_val(input: rig::client::ProviderValue) -> Self {
-        let rig::client::ProviderValue::Simple(api_key) = input else {
-            panic!("Incorrect provider value type")
+    #[test]
+    fn blocks_are_filtered_by_layout_type() {
+        let block = |layout_type: &str| Block {
+            layout_type: layout_type.to_string(),
+            ..Default::default()
         };
-        Self::new(&api_key)
+        let page = |blocks| ResultOcr {
+            text_ann: Annotation {
+                blocks: Some(blocks),
+                ..Default::default()
+            },
+            page: None,
+        };
+
+        let response = CompletionResponse::from_results(vec![
+            page(vec![block("LAYOUT_TYPE_TEXT"), block("LAYOUT_TYPE_TABLE")]),
+            page(vec![block("LAYOUT_TYPE_TABLE"), block("LAYOUT_TYPE_STAMP")]),
+        ])
+        .unwrap();
+
+        assert_eq!(response.blocks_of_type(LayoutType::Table).len(), 2);
+        assert_eq!(response.blocks_of_type("LAYOUT_TYPE_TEXT").len(), 1);
+        assert!(response.blocks_of_type(LayoutType::Header).is_empty());
+
+        let other = response.blocks_of_type("LAYOUT_TYPE_STAMP");
+        assert_eq!(
+            other[0].layout(),
+            LayoutType::Other("LAYOUT_TYPE_STAMP".to_string())
+        );
+        assert_eq!(other[0].layout().as_str(), "LAYOUT_TYPE_STAMP");
     }
-}
 
-impl CompletionClient for Client {
-    type CompletionModel = CompletionModel;
+    #[test]
+    fn only_jpeg_png_and_pdf_are_supported() {
+        assert!(supported_mime("image/jpeg"));
+        assert!(supported_mime("image/PNG"));
+        assert!(supported_mime("application/pdf"));
+        assert!(!supported_mime("image/gif"));
 
-    /// Creates a Yandex OCR model
-    fn completion_model(&self, model_name: &str) -> CompletionModel {
-        CompletionModel {
-            client: self.clone(),
-            model: model_name.to_string(),
+        assert!(checked_mime(message::ImageMediaType::WEBP.to_mime_type()).is_err());
+        assert_eq!(
+            checked_mime(message::DocumentMediaType::PDF.to_mime_type()).unwrap(),
+            "application/pdf"
+        );
+    }
+
+    #[tokio::test]
+    async fn pooled_client_serves_requests() {
+        let server = httpmock::MockServer::start_async().await;
+        let recognize = server
+            .mock_async(|when, then| {
+                when.method(httpmock::Method::POST).path("/recognizeText");
+                then.status(200).json_body(recognition("pooled"));
+            })
+            .await;
+
+        let model = Client::builder()
+            .api_key("key")
+            .base_url(&server.base_url())
+            .pool_max_idle_per_host(8)
+            .pool_idle_timeout(time::Duration::from_secs(30))
+            .tcp_keepalive(time::Duration::from_secs(60))
+            .build()
+            .unwrap()
+            .completion_model_typed(YandexModel::Page)
+            .mode(RecognitionMode::Sync);
+
+        for _ in 0..2 {
+            let response = model
+                .recognize_bytes(b"png", message::ImageMediaType::PNG)
+                .await;
+            assert_eq!(response.unwrap().full_text(), "pooled");
         }
+        recognize.assert_hits_async(2).await;
     }
-}
 
-impl VerifyClient for Client {
-    // #[cfg_attr(feature = "worker", worker::send)]
-    async fn verify(&self) -> Result<(), VerifyError> {
-        return Ok(());
+    #[tokio::test]
+    async fn default_headers_are_sent_with_every_request() {
+        let server = httpmock::MockServer::start_async().await;
+        let start = server
+            .mock_async(|when, then| {
+                when.method(httpmock::Method::POST)
+                    .path("/recognizeTextAsync")
+                    .header("x-request-source", "ocr-tests")
+                    .header("authorization", "Api-Key key");
+                then.status(200).json_body(operation("op"));
+            })
+            .await;
+        let poll = server
+            .mock_async(|when, then| {
+                when.method(httpmock::Method::GET)
+                    .path("/getRecognition")
+                    .header("x-request-source", "ocr-tests");
+                then.status(200).json_body(recognition("headers"));
+            })
+            .await;
+
+        let response = Client::builder()
+            .api_key("key")
+            .base_url(&server.base_url())
+            .header("x-request-source", "ocr-tests")
+            .header("Authorization", "overridden")
+            .build()
+            .unwrap()
+            .completion_model_typed(YandexModel::Page)
+            .poll_config(5, time::Duration::from_millis(10))
+            .completion(document_request(&BASE64_STANDARD.encode("document")))
+            .await
+            .unwrap();
+
+        assert_eq!(response.raw_response.full_text(), "headers");
+        start.assert_async().await;
+        poll.assert_async().await;
     }
-}
 
-impl_conversion_traits!(
-    AsEmbeddings,
-    AsTranscription,
-    AsImageGeneration,
-    AsAudioGeneration for Client
-);
+    #[test]
+    fn service_account_key_is_checked_by_the_builder() {
+        let dir = assert_fs::TempDir::new().unwrap();
+        let path = dir.path().join("key.json");
 
-#[derive(Debug, Deserialize)]
-struct ApiErrorResponse {
-    message: String,
-}
+        let missing = Client::builder()
+            .service_account_key(&path)
+            .header("x-proxy", "1")
+            .build();
+        assert!(matches!(missing, Err(YaErr::BuildErr(_))));
 
-#[derive(Debug, Deserialize)]
-#[serde(untagged)]
-enum ApiResponse<T> {
-    Ok(T),
-    Err(ApiErrorResponse),
-}
+        std::fs::write(&path, "not json").unwrap();
+        assert!(matches!(
+            Client::from_sa_key(&path),
+            Err(YaErr::ParseErr(_))
+        ));
 
-impl From<ApiErrorResponse> for CompletionError {
-    fn from(err: ApiErrorResponse) -> Self {
-        CompletionError::ProviderError(err.message)
+        // signing fails without a valid key, or without the feature
+        std::fs::write(
+            &path,
+            r#"{"id": "key", "service_account_id": "sa", "private_key": "none"}"#,
+        )
+        .unwrap();
+        assert!(matches!(
+            Client::builder()
+                .service_account_key(&path)
+                .region(Region::Kz)
+                .build(),
+            Err(YaErr::BuildErr(_))
+        ));
     }
-}
 
-/// The response shape from the Yandex API
-#[derive(Clone, Debug, Serialize, Deserialize)]
-pub struct CompletionResponse {
-    pub result: ResultOcr,
-}
+    #[test]
+    fn invalid_header_is_a_build_error() {
+        let res = Client::builder()
+            .api_key("key")
+            .header("x-bad header", "value")
+            .build();
 
-/// The struct implementing the `CompletionModel` trait
-#[derive(Clone)]
-pub struct CompletionModel {
-    pub client: Client,
-    pub model: String,
-}
+        assert!(matches!(res, Err(YaErr::BuildErr(_))));
+    }
 
-#[derive(Clone, Debug, Serialize, Deserialize, Default)]
-struct YaCompletionRequest {
-    #[serde(rename = "mimeType")]
-    mime_type: String,
+    #[test]
+    fn set_languages_keeps_the_list_valid() {
+        let mut client = Client::builder().api_key("key").build().unwrap();
 
-    #[serde(rename = "languageCodes")]
-    language_codes: Vec<String>,
-    model: String,
-    content: String, // base64 encoded
-}
+        assert!(matches!(
+            client.set_languages(vec![]),
+            Err(YaErr::ReqErr(_))
+        ));
+        assert!(client.set_languages(vec!["en us".to_string()]).is_err());
+        assert_eq!(client.languages(), ["ru"]);
 
-impl completion::CompletionModel for CompletionModel {
-    type Response = CompletionResponse;
-    type StreamingResponse = CompletionResponse;
+        client
+            .set_languages(vec!["en".to_string(), " kk ".to_string()])
+            .unwrap();
+        assert_eq!(client.languages(), ["en", "kk"]);
+    }
 
-    // #[cfg_attr(feature = "worker", worker::send)]
-    async fn completion(
-        &self,
-        completion_request: CompletionRequest,
-    ) -> Result<completion::CompletionResponse<CompletionResponse>, rig::completion::CompletionError>
-    {
-        // if completion_request.documents.len() != 1 {
-        //   return Err(CompletionError::RequestError(Box::new(
-        //     YaErr::ReqErr(format!(
-        //       "Can only send one document at a time {:?}",
-        //       completion_request
-        //     )),
-        //   )));
-        // }
-
-        let docs_msg = match completion_request.chat_history.first() {
-            message::Message::User { content } => {
-                // filter only documents
-                let docs = content
-                    .into_iter()
-                    .filter_map(|c| match c {
-                        message::UserContent::Document(doc) => Some(doc),
-                        _ => None,
-                    })
-                    .collect::<Vec<_>>();
+    #[tokio::test]
+    async fn http_client_builder_is_finished_by_the_crate() {
+        let server = httpmock::MockServer::start_async().await;
+        let recognize = server
+            .mock_async(|when, then| {
+                when.method(httpmock::Method::POST)
+                    .path("/recognizeText")
+                    .header("user-agent", "ocr-tests");
+                then.status(200).json_body(recognition("built"));
+            })
+            .await;
 
-                docs
-            }
+        let client = Client::from_full_with_http_builder(
+            Some(server.base_url()),
+            Some("key".to_string()),
+            None,
+            None,
+            None,
+            HttpClient::builder().user_agent("ocr-tests"),
+            None,
+        )
+        .unwrap();
+        let response = client
+            .completion_model_typed(YandexModel::Page)
+            .mode(RecognitionMode::Sync)
+            .recognize_bytes(b"png", message::ImageMediaType::PNG)
+            .await
+            .unwrap();
 
-            _ => {
-                return Err(CompletionError::RequestError(Box::new(YaErr::ReqErr(
-                    "Can only send documents".to_string(),
-                ))));
-            }
-        };
+        assert_eq!(response.full_text(), "built");
+        recognize.assert_async().await;
+    }
 
-        let imgs_msg = match completion_request.chat_history.first() {
-            message::Message::User { content } => {
-                // filter only documents
-                let docs = content
-                    .into_iter()
-                    .filter_map(|c| match c {
-                        message::UserContent::Image(doc) => Some(doc),
-                        _ => None,
-                    })
-                    .collect::<Vec<_>>();
+    #[tokio::test]
+    async fn user_agent_defaults_to_the_crate_and_can_be_set() {
+        let server = httpmock::MockServer::start_async().await;
+        let default = server
+            .mock_async(|when, then| {
+                when.method(httpmock::Method::POST)
+                    .path("/recognizeText")
+                    .header("user-agent", YA_USER_AGENT);
+                then.status(200).json_body(recognition("default"));
+            })
+            .await;
+        let custom = server
+            .mock_async(|when, then| {
+                when.method(httpmock::Method::POST)
+                    .path("/recognizeText")
+                    .header("user-agent", "ocr-pipeline/2");
+                then.status(200).json_body(recognition("custom"));
+            })
+            .await;
 
-                docs
-            }
+        for builder in [
+            Client::builder(),
+            Client::builder().user_agent("ocr-pipeline/2"),
+        ] {
+            builder
+                .api_key("key")
+                .base_url(&server.base_url())
+                .build()
+                .unwrap()
+                .completion_model_typed(YandexModel::Page)
+                .mode(RecognitionMode::Sync)
+                .recognize_bytes(b"png", message::ImageMediaType::PNG)
+                .await
+                .unwrap();
+        }
 
-            _ => {
-                return Err(CompletionError::RequestError(Box::new(YaErr::ReqErr(
-                    "Can only send documents".to_string(),
-                ))));
-            }
-        };
+        assert!(YA_USER_AGENT.starts_with("rig-yandex-ocr/"));
+        default.assert_async().await;
+        custom.assert_async().await;
+    }
 
-        let c;
-        let mime_t;
-        if docs_msg.len() > 0 {
-            c = Some(match docs_msg[0].data.clone() {
-                DocumentSourceKind::Base64(s) => s,
-                _ => {
-                    return Err(CompletionError::RequestError(Box::new(YaErr::ReqErr(
-                        "Should be base64 encoded".to_string(),
-                    ))));
-                }
-            });
-            mime_t = Some(
-                docs_msg[0]
-                    .clone()
-                    .media_type
-                    .unwrap()
-                    .to_mime_type()
-                    .to_string(),
-            );
-        } else if imgs_msg.len() > 0 {
-            c = Some(match imgs_msg[0].data.clone() {
-                DocumentSourceKind::Base64(s) => s,
-                _ => {
-                    return Err(CompletionError::RequestError(Box::new(YaErr::ReqErr(
-                        "Should be base64 encoded".to_string(),
-                    ))));
-                }
-            });
-            mime_t = Some(
-                imgs_msg[0]
-                    .clone()
-                    .media_type
-                    .unwrap()
-                    .to_mime_type()
-                    .to_string(),
-            );
-        } else {
-            c = None;
-            mime_t = None;
-        }
+    #[test]
+    fn invalid_token_pattern_is_a_build_error() {
+        let res = Client::builder()
+            .api_key("key")
+            .token_pattern("t1\\.[")
+            .build();
 
-        let c_f;
-        let mime_t_f;
-        match c {
-            Some(s) => {
-                c_f = s;
-                mime_t_f = mime_t.unwrap();
-            }
-            None => {
-                return Err(CompletionError::RequestError(Box::new(YaErr::ReqErr(
-                    "Incorrect msg - required Image or Doc".to_string(),
-                ))));
+        assert!(matches!(res, Err(YaErr::BuildErr(_))));
+    }
+
+    #[tokio::test]
+    async fn token_provider_replaces_the_cli() {
+        let client = Client::builder()
+            .token_provider(|| Ok("t1.provided".to_string()))
+            .build()
+            .unwrap();
+
+        assert_eq!(client.auth_t, AuthType::Provider);
+        assert_eq!(client.current_token().await.unwrap(), "t1.provided");
+    }
+
+    #[tokio::test]
+    async fn custom_token_source_is_used() {
+        struct Static;
+
+        impl TokenSource for Static {
+            fn issue_token(&self) -> Result<String, YaErr> {
+                Ok("t1.static".to_string())
             }
-        };
+        }
 
-        let request = YaCompletionRequest {
-            mime_type: mime_t_f,
-            language_codes: self.client.langs.clone(),
-            model: self.model.clone(),
-            content: c_f,
+        let client = Client::builder().token_source(Static).build().unwrap();
+        assert_eq!(client.auth_t, AuthType::Provider);
+        assert_eq!(client.current_token().await.unwrap(), "t1.static");
+    }
+
+    #[tokio::test]
+    async fn token_is_reissued_after_three_hours() {
+        let now = Local::now().naive_local();
+        let state = TokenState {
+            token: Some("t1.token".to_string()),
+            token_upd: Some(now),
+            expires_at: None,
         };
+        let within = now + YA_OCR_TOKEN_UPD - TimeDelta::minutes(1);
+        let after = now + YA_OCR_TOKEN_UPD + TimeDelta::minutes(1);
+        assert!(state.is_fresh(within, YA_TOKEN_REFRESH_MARGIN));
+        assert!(!state.is_fresh(after, YA_TOKEN_REFRESH_MARGIN));
 
-        tracing::trace!("Yandex completion request: {:?}", &request);
+        let issued = Arc::new(std::sync::atomic::AtomicUsize::new(0));
+        let counter = issued.clone();
+        let client = Client::builder()
+            .token_provider(move || {
+                let n = counter.fetch_add(1, std::sync::atomic::Ordering::SeqCst) + 1;
+                Ok(format!("t1.token{}", n))
+            })
+            .build()
+            .unwrap();
 
-        let response_init;
-        unsafe {
-            let cli = &self.client as *const Client as *mut Client;
-            let bld = <*mut Client>::as_mut(cli)
-                .unwrap()
-                .post("/recognizeTextAsync");
+        assert_eq!(client.current_token().await.unwrap(), "t1.token1");
+        assert_eq!(client.current_token().await.unwrap(), "t1.token1");
 
-            response_init = bld
-                .json(&request)
-                .send()
-                .await
-                .expect("Could not init request");
-        }
+        // pretend the token was issued just over 3 hours ago
+        client.token.lock().await.token_upd =
+            Some(Local::now().naive_local() - YA_OCR_TOKEN_UPD - TimeDelta::minutes(1));
+        assert_eq!(client.current_token().await.unwrap(), "t1.token2");
+        assert_eq!(issued.load(std::sync::atomic::Ordering::SeqCst), 2);
+    }
 
-        let resp;
-        if response_init.status().is_success() {
-            let t = response_init
-                .text()
-                .await
-                .expect("Could not extract text")
-                .to_string();
-            tracing::trace!(target: "rig", "Yandex req echo: {}", t);
+    #[test]
+    fn known_expiry_overrides_the_refresh_window() {
+        let now = Local::now().naive_local();
+        let state = TokenState {
+            token: Some("t1.token".to_string()),
+            token_upd: Some(now),
+            expires_at: Some(now + TimeDelta::minutes(10)),
+        };
 
-            resp = serde_json::from_str::<AsyncRes>(&t)?;
-        } else {
-            return Err(CompletionError::ProviderError(
-                response_init
-                    .text()
-                    .await
-                    .unwrap_or("Not known error".to_string()),
-            ));
-        }
+        assert!(state.is_fresh(now + TimeDelta::minutes(4), YA_TOKEN_REFRESH_MARGIN));
+        assert!(!state.is_fresh(now + TimeDelta::minutes(6), YA_TOKEN_REFRESH_MARGIN));
+        assert!(!state.is_fresh(now + TimeDelta::minutes(11), TimeDelta::zero()));
+    }
 
-        let mut response = None;
-        let req = format!("/getRecognition?operationId={}", resp.id);
-        tracing::trace!("Sending msg to get reeocg: {}", req);
-        for i in 0..30 {
-            tracing::trace!("Yandex {} attempt to get res", i + 1);
+    #[test]
+    fn an_overflowing_margin_treats_the_token_as_stale() {
+        let now = Local::now().naive_local();
+        let state = TokenState {
+            token: Some("t1.token".to_string()),
+            token_upd: Some(now),
+            expires_at: Some(now + TimeDelta::minutes(10)),
+        };
+
+        assert!(!state.is_fresh(now, TimeDelta::MAX));
+    }
 
-            let loc_res;
-            unsafe {
-                let cli = &self.client as *const Client as *mut Client;
-                let bld = <*mut Client>::as_mut(cli).unwrap().get(req.as_str());
+    #[test]
+    fn token_lifetime_out_of_range_is_a_parse_error() {
+        let now = Local::now().naive_local();
+        assert_eq!(token_expiry(now, 3600).unwrap(), now + TimeDelta::hours(1));
+        assert!(matches!(
+            token_expiry(now, i64::MAX),
+            Err(YaErr::ParseErr(_))
+        ));
+        assert!(matches!(
+            token_expiry(now, i64::MIN),
+            Err(YaErr::ParseErr(_))
+        ));
+    }
 
-                loc_res = bld.json(&req).send().await.expect("Could not get response");
-            }
+    #[test]
+    fn transport_errors_keep_their_source() {
+        let err: YaErr = HttpClient::new()
+            .get("not a url")
+            .build()
+            .unwrap_err()
+            .into();
 
-            if loc_res.status().is_success() {
-                response = Some(loc_res);
-                break;
-            }
+        let source = std::error::Error::source(&err).unwrap();
+        assert!(
+            source
+                .downcast_ref::<reqwest::Error>()
+                .unwrap()
+                .is_builder()
+        );
+    }
 
-            tracing::trace!(
-                "Failed to get yandex recogn: {}",
-                loc_res.text().await.unwrap_or("no_text".to_string())
-            );
-            thread::sleep(time::Duration::from_millis(600));
-        }
+    #[test]
+    fn ya_errors_convert_into_completion_errors() {
+        let err = CompletionError::from(YaErr::TokenUpdErr("expired".to_string()));
+        let CompletionError::RequestError(source) = err else {
+            panic!("unexpected error: {err}");
+        };
+        assert!(matches!(
+            source.downcast_ref::<YaErr>(),
+            Some(YaErr::TokenUpdErr(_))
+        ));
 
-        if response.is_some() {
-            let t = response.unwrap().text().await.unwrap();
-            tracing::trace!(target: "rig", "Yandex completion: {}", t);
+        let err = CompletionError::from(YaErr::ParseErr("not json".to_string()));
+        assert!(matches!(err, CompletionError::ResponseError(m) if m == "not json"));
+    }
 
-            match serde_json::from_str::<ApiResponse<CompletionResponse>>(&t)? {
-                ApiResponse::Ok(response) => {
-                    tracing::trace!("ready to try_into");
-                    response.try_into()
-                }
-                ApiResponse::Err(err) => Err(CompletionError::ProviderError(err.message)),
-            }
-        } else {
-            Err(CompletionError::ProviderError(
-                "Could not get Async results".to_string(),
-            ))
+    #[test]
+    fn urls_are_joined_with_a_single_slash() {
+        let expected = "https://ocr.api.cloud.yandex.net/ocr/v1/recognizeText";
+        for base in [YA_BASE_URL, "https://ocr.api.cloud.yandex.net/ocr/v1/"] {
+            assert_eq!(join_url(base, "/recognizeText"), expected);
+            assert_eq!(join_url(base, "recognizeText"), expected);
         }
     }
 
-    async fn stream(
-        &self,
-        _: CompletionRequest,
-    ) -> Result<rig::streaming::StreamingCompletionResponse<Self::StreamingResponse>, CompletionError>
-    {
-        return Err(CompletionError::RequestError(Box::new(YaErr::ReqErr(
-            "Cannot send streaming request".to_string(),
-        ))));
+    #[test]
+    fn region_sets_the_ocr_and_iam_urls() {
+        let client = Client::builder()
+            .api_key("key")
+            .region(Region::Kz)
+            .build()
+            .unwrap();
+        assert_eq!(client.base_url, "https://ocr.api.yandexcloud.kz/ocr/v1");
+        assert_eq!(
+            client.iam_url,
+            "https://iam.api.yandexcloud.kz/iam/v1/tokens"
+        );
+
+        let client = Client::builder()
+            .api_key("key")
+            .region(Region::Kz)
+            .base_url("http://localhost:8080")
+            .build()
+            .unwrap();
+        assert_eq!(client.base_url, "http://localhost:8080");
+        assert_eq!(client.iam_url, Region::Kz.iam_url());
     }
-}
 
-impl GetTokenUsage for CompletionResponse {
-    fn token_usage(&self) -> Option<rig::completion::Usage> {
-        let mut usage = rig::completion::Usage::new();
-        usage.input_tokens = 0;
-        usage.output_tokens = 0;
-        usage.total_tokens = 0;
+    #[test]
+    fn languages_are_trimmed_and_validated() {
+        let langs = validate_languages(&[" ru ".to_string(), "zh-Hans".to_string()]).unwrap();
+        assert_eq!(langs, vec!["ru", "zh-Hans"]);
 
-        Some(usage)
+        assert!(validate_languages(&[]).is_err());
+        assert!(validate_languages(&["russian".to_string()]).is_err());
+        assert!(validate_languages(&["r1".to_string()]).is_err());
     }
-}
 
-impl TryFrom<CompletionResponse> for completion::CompletionResponse<CompletionResponse> {
-    type Error = CompletionError;
+    #[test]
+    fn auto_detect_language_sends_the_sentinel() {
+        let client = Client::builder()
+            .api_key("key")
+            .auto_detect_language()
+            .build()
+            .unwrap();
+        assert_eq!(client.languages(), ["*"]);
 
-    fn try_from(response: CompletionResponse) -> Result<Self, Self::Error> {
-        tracing::trace!("TRYING FROM");
-        let choice = OneOrMany::one(AssistantContent::text(format!(
-            "ENTITIES:{}\n\nMARKDOWN:{}\n\nFULL_TEXT:{}",
-            serde_json::to_string(&response.result.text_ann.entities).unwrap(),
-            // serde_json::to_string(&response.result.text_ann.tables).unwrap(),
-            serde_json::to_string(&response.result.text_ann.markdown).unwrap(),
-            serde_json::to_string(&response.result.text_ann.full_text).unwrap(),
-        )));
-        let usage = completion::Usage {
-            input_tokens: 0,
-            output_tokens: 0,
-            total_tokens: 0,
-        };
+        assert!(validate_languages(&[" * ".to_string(), "en".to_string()]).is_ok());
+        assert!(validate_languages(&["**".to_string()]).is_err());
+    }
 
-        Ok(completion::CompletionResponse {
-            choice,
-            usage,
-            raw_response: response,
-        })
+    #[test]
+    fn retry_after_accepts_seconds_and_dates() {
+        use reqwest::header::{HeaderMap, HeaderValue, RETRY_AFTER};
+
+        let mut headers = HeaderMap::new();
+        assert_eq!(retry_after(&headers), None);
+
+        headers.insert(RETRY_AFTER, HeaderValue::from_static("3"));
+        assert_eq!(retry_after(&headers), Some(time::Duration::from_secs(3)));
+
+        headers.insert(
+            RETRY_AFTER,
+            HeaderValue::from_static("Wed, 21 Oct 2015 07:28:00 GMT"),
+        );
+        assert_eq!(retry_after(&headers), Some(time::Duration::ZERO));
+
+        // hours asked by a misbehaving server are cut short
+        headers.insert(RETRY_AFTER, HeaderValue::from_static("86400"));
+        assert_eq!(retry_after(&headers), Some(YA_MAX_RETRY_DELAY));
+
+        headers.insert(RETRY_AFTER, HeaderValue::from_static("soon"));
+        assert_eq!(retry_after(&headers), None);
+    }
+
+    #[test]
+    fn oauth_clients_exchange_the_token_natively() {
+        let client = Client::try_from_oauth("folder", "oauth-token").unwrap();
+        assert_eq!(client.auth_t, AuthType::OAuth);
+        assert_eq!(client.oauth.as_deref(), Some("oauth-token"));
+    }
+
+    #[test]
+    fn empty_languages_are_rejected_on_build() {
+        let res = Client::from_full(
+            None,
+            Some("key".to_string()),
+            None,
+            None,
+            None,
+            None,
+            Some(vec![]),
+        );
+        let err = res.unwrap_err();
+        assert!(matches!(err, YaErr::BuildErr(_)));
+        assert_eq!(
+            err.to_string(),
+            "Error on construct: Language list is empty"
+        );
     }
 }
@@ -0,0 +1,81 @@
+// crops of the scans matching the recognized regions
+use crate::providers::yandex::client::YaErr;
+use crate::providers::yandex::schemas::BoundingBox;
+use std::io::Cursor;
+
+/// Crops the axis-aligned rectangle around `bbox` out of a JPEG or PNG scan,
+/// e.g. to process a detected table or picture further. The crop is encoded
+/// in the format of the scan; the parts of the box outside of the image are
+/// cut off.
+pub fn crop_region(image_bytes: &[u8], bbox: &BoundingBox) -> Result<Vec<u8>, YaErr> {
+    let format = image::guess_format(image_bytes)
+        .map_err(|e| YaErr::ParseErr(format!("Unknown image format: {}", e)))?;
+    let image = image::load_from_memory_with_format(image_bytes, format)
+        .map_err(|e| YaErr::ParseErr(format!("Could not decode the image: {}", e)))?;
+
+    let (left, top, right, bottom) = bbox.extent();
+    let clamp = |v: i64, max: u32| v.clamp(0, max as i64) as u32;
+    let (left, right) = (clamp(left, image.width()), clamp(right, image.width()));
+    let (top, bottom) = (clamp(top, image.height()), clamp(bottom, image.height()));
+    if left >= right || top >= bottom {
+        return Err(YaErr::ReqErr(format!(
+            "Bounding box {:?} lies outside of the {}x{} image",
+            bbox.vertices,
+            image.width(),
+            image.height()
+        )));
+    }
+
+    let crop = image.crop_imm(left, top, right - left, bottom - top);
+    let mut out = Cursor::new(Vec::new());
+    crop.write_to(&mut out, format)
+        .map_err(|e| YaErr::ParseErr(format!("Could not encode the crop: {}", e)))?;
+
+    Ok(out.into_inner())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::providers::yandex::schemas::Vertex;
+    use image::{GenericImageView, ImageFormat, Rgb, RgbImage};
+
+    fn bbox(points: [(i64, i64); 4]) -> BoundingBox {
+        BoundingBox {
+            vertices: points.map(|(x, y)| Vertex { x, y }).to_vec(),
+        }
+    }
+
+    fn png() -> Vec<u8> {
+        let image = RgbImage::from_fn(20, 10, |x, y| Rgb([x as u8, y as u8, 0]));
+        let mut out = Cursor::new(Vec::new());
+        image.write_to(&mut out, ImageFormat::Png).unwrap();
+        out.into_inner()
+    }
+
+    #[test]
+    fn region_is_cropped_from_the_rotated_box() {
+        let crop = crop_region(&png(), &bbox([(5, 2), (12, 3), (11, 8), (4, 7)])).unwrap();
+
+        assert_eq!(image::guess_format(&crop).unwrap(), ImageFormat::Png);
+        let crop = image::load_from_memory(&crop).unwrap();
+        assert_eq!(crop.dimensions(), (8, 6));
+        assert_eq!(crop.get_pixel(0, 0).0, [4, 2, 0, 255]);
+    }
+
+    #[test]
+    fn region_is_clamped_to_the_image() {
+        let crop = crop_region(&png(), &bbox([(15, -5), (30, -5), (30, 4), (15, 4)])).unwrap();
+        let crop = image::load_from_memory(&crop).unwrap();
+        assert_eq!(crop.dimensions(), (5, 4));
+
+        assert!(matches!(
+            crop_region(&png(), &bbox([(25, 0), (30, 0), (30, 5), (25, 5)])),
+            Err(YaErr::ReqErr(_))
+        ));
+        assert!(matches!(
+            crop_region(b"GIF89a", &bbox([(0, 0); 4])),
+            Err(YaErr::ParseErr(_))
+        ));
+    }
+}
@@ -0,0 +1,324 @@
+// hOCR and ALTO renderings of recognition results
+use crate::providers::yandex::client::CompletionResponse;
+use crate::providers::yandex::schemas::*;
+
+const HOCR_HEAD: &str = r#"<?xml version="1.0" encoding="UTF-8"?>
+<!DOCTYPE html PUBLIC "-//W3C//DTD XHTML 1.0 Transitional//EN" "http://www.w3.org/TR/xhtml1/DTD/xhtml1-transitional.dtd">
+<html xmlns="http://www.w3.org/1999/xhtml" xml:lang="en" lang="en">
+ <head>
+  <title></title>
+  <meta http-equiv="Content-Type" content="text/html;charset=utf-8"/>
+  <meta name="ocr-system" content="yandex-ocr"/>
+  <meta name="ocr-capabilities" content="ocr_page ocr_carea ocr_line ocrx_word"/>
+ </head>
+ <body>
+"#;
+
+const ALTO_HEAD: &str = r#"<?xml version="1.0" encoding="UTF-8"?>
+<alto xmlns="http://www.loc.gov/standards/alto/ns-v4#" xmlns:xsi="http://www.w3.org/2001/XMLSchema-instance" xsi:schemaLocation="http://www.loc.gov/standards/alto/ns-v4# http://www.loc.gov/standards/alto/v4/alto-4-2.xsd">
+  <Description>
+    <MeasurementUnit>pixel</MeasurementUnit>
+    <OCRProcessing ID="OCR_0">
+      <ocrProcessingStep>
+        <processingSoftware>
+          <softwareName>Yandex OCR</softwareName>
+        </processingSoftware>
+      </ocrProcessingStep>
+    </OCRProcessing>
+  </Description>
+  <Layout>
+"#;
+
+// text escaped for XML character data and attribute values
+fn escape_xml(text: &str) -> String {
+    let mut out = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => out.push_str("&amp;"),
+            '<' => out.push_str("&lt;"),
+            '>' => out.push_str("&gt;"),
+            '"' => out.push_str("&quot;"),
+            '\'' => out.push_str("&apos;"),
+            c => out.push(c),
+        }
+    }
+    out
+}
+
+// width and height of the page, derived from its blocks when Yandex left
+// them out
+fn page_size(ann: &Annotation) -> (i64, i64) {
+    let (right, bottom) = ann
+        .blocks
+        .iter()
+        .flatten()
+        .map(|b| b.bounding_box.extent())
+        .fold((0, 0), |(r, b), (_, _, right, bottom)| {
+            (r.max(right), b.max(bottom))
+        });
+    (ann.width.unwrap_or(right), ann.height.unwrap_or(bottom))
+}
+
+fn hocr_bbox(bounding_box: &BoundingBox) -> String {
+    let (left, top, right, bottom) = bounding_box.extent();
+    format!("bbox {} {} {} {}", left, top, right, bottom)
+}
+
+// ALTO position attributes of the extent of the vertices
+fn alto_box(bounding_box: &BoundingBox) -> String {
+    let (left, top, right, bottom) = bounding_box.extent();
+    format!(
+        "HPOS=\"{}\" VPOS=\"{}\" WIDTH=\"{}\" HEIGHT=\"{}\"",
+        left,
+        top,
+        right - left,
+        bottom - top
+    )
+}
+
+impl CompletionResponse {
+    /// Renders all pages as an hOCR document: an `ocr_page` per page holding
+    /// the `ocr_carea` blocks, `ocr_line` lines and `ocrx_word` words, boxed
+    /// by the extent of their vertices. Word confidence is reported as
+    /// `x_wconf` when the model returns it.
+    pub fn to_hocr(&self) -> String {
+        let mut out = String::from(HOCR_HEAD);
+        for (p, page) in self.pages().iter().enumerate() {
+            let ann = &page.text_ann;
+            let (width, height) = page_size(ann);
+            out.push_str(&format!(
+                "  <div class='ocr_page' id='page_{}' title='bbox 0 0 {} {}; ppageno {}'>\n",
+                p + 1,
+                width,
+                height,
+                p
+            ));
+
+            for (b, block) in ann.blocks.iter().flatten().enumerate() {
+                let id = format!("{}_{}", p + 1, b + 1);
+                out.push_str(&format!(
+                    "   <div class='ocr_carea' id='block_{}' title='{}'>\n",
+                    id,
+                    hocr_bbox(&block.bounding_box)
+                ));
+
+                for (l, line) in block.lines.iter().enumerate() {
+                    let id = format!("{}_{}", id, l + 1);
+                    out.push_str(&format!(
+                        "    <span class='ocr_line' id='line_{}' title='{}'>",
+                        id,
+                        hocr_bbox(&line.bounding_box)
+                    ));
+
+                    // lines of models not reporting words keep their text
+                    if line.words.is_empty() {
+                        out.push_str(&escape_xml(&line.text));
+                    }
+                    for (w, word) in line.words.iter().enumerate() {
+                        let mut title = hocr_bbox(&word.bounding_box);
+                        if let Some(confidence) = word.confidence {
+                            title.push_str(&format!(
+                                "; x_wconf {}",
+                                (confidence * 100.0).round() as i64
+                            ));
+                        }
+                        out.push_str(&format!(
+                            "\n     <span class='ocrx_word' id='word_{}_{}' title='{}'>{}</span>",
+                            id,
+                            w + 1,
+                            title,
+                            escape_xml(&word.text)
+                        ));
+                    }
+                    out.push_str("</span>\n");
+                }
+                out.push_str("   </div>\n");
+            }
+            out.push_str("  </div>\n");
+        }
+        out.push_str(" </body>\n</html>\n");
+        out
+    }
+
+    /// Renders all pages as an ALTO v4 document measured in pixels: a `Page`
+    /// per page sized by `Annotation::width` and `height`, holding the
+    /// `TextBlock`, `TextLine` and `String` elements positioned by the
+    /// extent of their vertices. Word confidence is reported as `WC` when
+    /// the model returns it.
+    pub fn to_alto(&self) -> String {
+        let mut out = String::from(ALTO_HEAD);
+        for (p, page) in self.pages().iter().enumerate() {
+            let ann = &page.text_ann;
+            let (width, height) = page_size(ann);
+            out.push_str(&format!(
+                "    <Page ID=\"page_{}\" PHYSICAL_IMG_NR=\"{}\" WIDTH=\"{}\" HEIGHT=\"{}\">\n",
+                p + 1,
+                p + 1,
+                width,
+                height
+            ));
+            out.push_str(&format!(
+                "      <PrintSpace HPOS=\"0\" VPOS=\"0\" WIDTH=\"{}\" HEIGHT=\"{}\">\n",
+                width, height
+            ));
+
+            for (b, block) in ann.blocks.iter().flatten().enumerate() {
+                let id = format!("{}_{}", p + 1, b + 1);
+                out.push_str(&format!(
+                    "        <TextBlock ID=\"block_{}\" {}>\n",
+                    id,
+                    alto_box(&block.bounding_box)
+                ));
+
+                for (l, line) in block.lines.iter().enumerate() {
+                    let id = format!("{}_{}", id, l + 1);
+                    out.push_str(&format!(
+                        "          <TextLine ID=\"line_{}\" {}>\n",
+                        id,
+                        alto_box(&line.bounding_box)
+                    ));
+
+                    // lines of models not reporting words make a single string
+                    if line.words.is_empty() {
+                        out.push_str(&format!(
+                            "            <String ID=\"string_{}_1\" CONTENT=\"{}\" {}/>\n",
+                            id,
+                            escape_xml(&line.text),
+                            alto_box(&line.bounding_box)
+                        ));
+                    }
+                    for (w, word) in line.words.iter().enumerate() {
+                        if w > 0 {
+                            out.push_str("            <SP/>\n");
+                        }
+                        let confidence = word
+                            .confidence
+                            .map(|c| format!(" WC=\"{:.2}\"", c.clamp(0.0, 1.0)))
+                            .unwrap_or_default();
+                        out.push_str(&format!(
+                            "            <String ID=\"string_{}_{}\" CONTENT=\"{}\" {}{}/>\n",
+                            id,
+                            w + 1,
+                            escape_xml(&word.text),
+                            alto_box(&word.bounding_box),
+                            confidence
+                        ));
+                    }
+                    out.push_str("          </TextLine>\n");
+                }
+                out.push_str("        </TextBlock>\n");
+            }
+            out.push_str("      </PrintSpace>\n    </Page>\n");
+        }
+        out.push_str("  </Layout>\n</alto>\n");
+        out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // page with a single "R&D 2024" line of two words
+    fn response() -> CompletionResponse {
+        let vertices = |l: i64, t: i64, r: i64, b: i64| {
+            serde_json::json!({ "vertices": [
+                { "x": l.to_string(), "y": t.to_string() },
+                { "x": l.to_string(), "y": b.to_string() },
+                { "x": r.to_string(), "y": b.to_string() },
+                { "x": r.to_string(), "y": t.to_string() }
+            ] })
+        };
+        let word = |text: &str, bbox, confidence| {
+            serde_json::json!({
+                "boundingBox": bbox,
+                "text": text,
+                "entityIndex": "-1",
+                "textSegments": [],
+                "confidence": confidence
+            })
+        };
+
+        serde_json::from_value(serde_json::json!({
+            "result": {
+                "textAnnotation": {
+                    "width": "1000",
+                    "height": "500",
+                    "blocks": [{
+                        "boundingBox": vertices(10, 20, 200, 40),
+                        "lines": [{
+                            "boundingBox": vertices(10, 20, 200, 40),
+                            "text": "R&D 2024",
+                            "words": [
+                                word("R&D", vertices(10, 20, 90, 40), 0.984),
+                                word("2024", vertices(110, 20, 200, 40), 0.5)
+                            ],
+                            "textSegments": [],
+                            "orientation": "ANGLE_0"
+                        }],
+                        "languages": [{ "languageCode": "en" }],
+                        "textSegments": [],
+                        "layoutType": "LAYOUT_TYPE_TEXT"
+                    }],
+                    "fullText": "R&D 2024"
+                },
+                "page": "0"
+            }
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn hocr_boxes_pages_blocks_lines_and_words() {
+        let hocr = response().to_hocr();
+
+        assert!(hocr.starts_with("<?xml"));
+        assert!(hocr.contains("class='ocr_page' id='page_1' title='bbox 0 0 1000 500; ppageno 0'"));
+        assert!(hocr.contains("class='ocr_carea' id='block_1_1' title='bbox 10 20 200 40'"));
+        assert!(hocr.contains("class='ocr_line' id='line_1_1_1' title='bbox 10 20 200 40'"));
+        assert!(hocr.contains(
+            "<span class='ocrx_word' id='word_1_1_1_1' title='bbox 10 20 90 40; x_wconf 98'>R&amp;D</span>"
+        ));
+        assert!(hocr.contains("title='bbox 110 20 200 40; x_wconf 50'>2024</span>"));
+        assert!(hocr.trim_end().ends_with("</html>"));
+    }
+
+    #[test]
+    fn alto_positions_blocks_lines_and_strings() {
+        let alto = response().to_alto();
+
+        assert!(alto.contains(r#"<MeasurementUnit>pixel</MeasurementUnit>"#));
+        assert!(
+            alto.contains(r#"<Page ID="page_1" PHYSICAL_IMG_NR="1" WIDTH="1000" HEIGHT="500">"#)
+        );
+        assert!(
+            alto.contains(
+                r#"<TextBlock ID="block_1_1" HPOS="10" VPOS="20" WIDTH="190" HEIGHT="20">"#
+            )
+        );
+        assert!(alto.contains(
+            r#"<String ID="string_1_1_1_1" CONTENT="R&amp;D" HPOS="10" VPOS="20" WIDTH="80" HEIGHT="20" WC="0.98"/>"#
+        ));
+        assert!(alto.contains("<SP/>"));
+        assert!(
+            alto.contains(
+                r#"CONTENT="2024" HPOS="110" VPOS="20" WIDTH="90" HEIGHT="20" WC="0.50"/>"#
+            )
+        );
+        assert!(alto.trim_end().ends_with("</alto>"));
+    }
+
+    #[test]
+    fn page_size_falls_back_to_the_blocks() {
+        let mut response = response();
+        response.result.text_ann.width = None;
+        response.result.text_ann.height = None;
+        response.results.clear();
+
+        assert!(
+            response
+                .to_hocr()
+                .contains("title='bbox 0 0 200 40; ppageno 0'")
+        );
+    }
+}
@@ -1,2 +1,7 @@
 pub mod client;
-mod schemas;
+#[cfg(feature = "yandex-crop")]
+pub mod crop;
+mod export;
+#[cfg(feature = "pdf")]
+mod pdf;
+pub mod schemas;
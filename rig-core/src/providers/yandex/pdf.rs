@@ -0,0 +1,431 @@
+// searchable PDF: the scanned image with the recognized words laid over it
+// as invisible text
+use crate::providers::yandex::client::{CompletionResponse, YaErr};
+use crate::providers::yandex::schemas::*;
+use lopdf::content::{Content, Operation};
+use lopdf::{Dictionary, Document, Object, ObjectId, Stream, StringFormat, dictionary};
+
+// width of every glyph of the text font, in thousandths of the font size
+const GLYPH_WIDTH: f32 = 500.0;
+
+// scan embedded without decoding, the PDF viewer doing it
+struct PdfImage {
+    width: i64,
+    height: i64,
+    dict: Dictionary,
+    data: Vec<u8>,
+}
+
+// a JPEG is embedded as is, its size and color space read from the frame
+// header
+fn jpeg_image(data: &[u8]) -> Option<PdfImage> {
+    if !data.starts_with(&[0xFF, 0xD8]) {
+        return None;
+    }
+
+    let mut pos = 2;
+    while pos + 4 <= data.len() {
+        if data[pos] != 0xFF {
+            return None;
+        }
+        let marker = data[pos + 1];
+        // fill bytes and the markers without a length
+        if marker == 0xFF {
+            pos += 1;
+            continue;
+        }
+        if marker == 0x01 || (0xD0..=0xD9).contains(&marker) {
+            pos += 2;
+            continue;
+        }
+
+        // start of frame markers, DHT, JPG and DAC aside
+        if (0xC0..=0xCF).contains(&marker) && !matches!(marker, 0xC4 | 0xC8 | 0xCC) {
+            let frame = data.get(pos + 4..pos + 10)?;
+            let color_space = match frame[5] {
+                1 => "DeviceGray",
+                3 => "DeviceRGB",
+                4 => "DeviceCMYK",
+                _ => return None,
+            };
+            return Some(PdfImage {
+                width: u16::from_be_bytes([frame[3], frame[4]]) as i64,
+                height: u16::from_be_bytes([frame[1], frame[2]]) as i64,
+                dict: dictionary! {
+                    "ColorSpace" => color_space,
+                    "BitsPerComponent" => 8,
+                    "Filter" => "DCTDecode",
+                },
+                data: data.to_vec(),
+            });
+        }
+
+        let len = u16::from_be_bytes([data[pos + 2], data[pos + 3]]) as usize;
+        pos += 2 + len;
+    }
+    None
+}
+
+// the zlib stream of a PNG is embedded as is, PDF supporting the PNG
+// predictors; palette, alpha and interlaced images would need decoding
+fn png_image(data: &[u8]) -> Option<PdfImage> {
+    let mut chunks = data.strip_prefix(b"\x89PNG\r\n\x1a\n")?;
+    let mut header = None;
+    let mut idat = Vec::new();
+    while chunks.len() >= 12 {
+        let len = u32::from_be_bytes(chunks[0..4].try_into().ok()?) as usize;
+        let body = chunks.get(8..8 + len)?;
+        match &chunks[4..8] {
+            b"IHDR" => header = Some(body),
+            b"IDAT" => idat.extend_from_slice(body),
+            b"IEND" => break,
+            _ => {}
+        }
+        chunks = chunks.get(12 + len..)?;
+    }
+
+    let header = header.filter(|h| h.len() >= 13)?;
+    let width = u32::from_be_bytes(header[0..4].try_into().ok()?) as i64;
+    let height = u32::from_be_bytes(header[4..8].try_into().ok()?) as i64;
+    let (bits, color_type, interlace) = (header[8] as i64, header[9], header[12]);
+    let (colors, color_space) = match (color_type, interlace) {
+        (0, 0) => (1, "DeviceGray"),
+        (2, 0) => (3, "DeviceRGB"),
+        _ => return None,
+    };
+
+    Some(PdfImage {
+        width,
+        height,
+        dict: dictionary! {
+            "ColorSpace" => color_space,
+            "BitsPerComponent" => bits,
+            "Filter" => "FlateDecode",
+            "DecodeParms" => dictionary! {
+                "Predictor" => 15,
+                "Colors" => colors,
+                "BitsPerComponent" => bits,
+                "Columns" => width,
+            },
+        },
+        data: idat,
+    })
+}
+
+// font of the text layer: never drawn, its codes are the UTF-16 code units
+// of the text mapped back to Unicode for search and copy
+fn add_text_font(document: &mut Document) -> ObjectId {
+    let mut cmap = String::from(
+        "/CIDInit /ProcSet findresource begin\n12 dict begin\nbegincmap\n\
+         /CIDSystemInfo << /Registry (Adobe) /Ordering (UCS) /Supplement 0 >> def\n\
+         /CMapName /Adobe-Identity-UCS def\n/CMapType 2 def\n\
+         1 begincodespacerange\n<0000> <FFFF>\nendcodespacerange\n",
+    );
+    // a range may only vary in its last byte and a block holds 100 of them
+    let high_bytes: Vec<u32> = (0..256).collect();
+    for block in high_bytes.chunks(100) {
+        cmap.push_str(&format!("{} beginbfrange\n", block.len()));
+        for high in block {
+            cmap.push_str(&format!("<{high:02X}00> <{high:02X}FF> <{high:02X}00>\n"));
+        }
+        cmap.push_str("endbfrange\n");
+    }
+    cmap.push_str("endcmap\nCMapName currentdict /CMap defineresource pop\nend\nend\n");
+    let to_unicode = document.add_object(Stream::new(dictionary! {}, cmap.into_bytes()));
+
+    let descriptor = document.add_object(dictionary! {
+        "Type" => "FontDescriptor",
+        "FontName" => "GlyphLessFont",
+        "Flags" => 5,
+        "FontBBox" => vec![0.into(), 0.into(), (GLYPH_WIDTH as i64).into(), 1000.into()],
+        "ItalicAngle" => 0,
+        "Ascent" => 1000,
+        "Descent" => 0,
+        "CapHeight" => 1000,
+        "StemV" => 80,
+    });
+    let cid_font = document.add_object(dictionary! {
+        "Type" => "Font",
+        "Subtype" => "CIDFontType2",
+        "BaseFont" => "GlyphLessFont",
+        "CIDSystemInfo" => dictionary! {
+            "Registry" => Object::string_literal("Adobe"),
+            "Ordering" => Object::string_literal("Identity"),
+            "Supplement" => 0,
+        },
+        "FontDescriptor" => descriptor,
+        "DW" => GLYPH_WIDTH as i64,
+        "CIDToGIDMap" => "Identity",
+    });
+    document.add_object(dictionary! {
+        "Type" => "Font",
+        "Subtype" => "Type0",
+        "BaseFont" => "GlyphLessFont",
+        "Encoding" => "Identity-H",
+        "DescendantFonts" => vec![cid_font.into()],
+        "ToUnicode" => to_unicode,
+    })
+}
+
+// operations drawing the word as invisible text stretched over its box,
+// the baseline at the bottom
+fn word_operations(word: &Word, scale: (f32, f32), page_height: f32) -> Vec<Operation> {
+    // characters outside the BMP would need surrogate pairs
+    let codes: Vec<u16> = word
+        .text
+        .chars()
+        .map(|c| u16::try_from(c as u32).unwrap_or(0xFFFD))
+        .collect();
+    let (left, top, right, bottom) = word.bounding_box.extent();
+    let (left, right) = (left as f32 * scale.0, right as f32 * scale.0);
+    let (top, bottom) = (top as f32 * scale.1, bottom as f32 * scale.1);
+    if codes.is_empty() || right <= left || bottom <= top {
+        return Vec::new();
+    }
+
+    let size = bottom - top;
+    let natural_width = codes.len() as f32 * GLYPH_WIDTH / 1000.0 * size;
+    let text = codes.iter().flat_map(|c| c.to_be_bytes()).collect();
+
+    vec![
+        Operation::new("Tf", vec!["F0".into(), size.into()]),
+        Operation::new("Tz", vec![(100.0 * (right - left) / natural_width).into()]),
+        Operation::new(
+            "Tm",
+            vec![
+                1.into(),
+                0.into(),
+                0.into(),
+                1.into(),
+                left.into(),
+                (page_height - bottom).into(),
+            ],
+        ),
+        Operation::new("Tj", vec![Object::String(text, StringFormat::Hexadecimal)]),
+    ]
+}
+
+impl CompletionResponse {
+    /// Makes a searchable PDF of the first page out of its original JPEG or
+    /// PNG scan: the image with the recognized words laid over it as
+    /// invisible text, so the PDF can be searched and copied from. The page
+    /// is one point per image pixel, the word boxes being scaled from the
+    /// page size Yandex reported. PNGs must be non-interlaced grayscale or
+    /// RGB without alpha. Requires the `pdf` feature.
+    pub fn to_searchable_pdf(&self, image: &[u8]) -> Result<Vec<u8>, YaErr> {
+        let image = jpeg_image(image)
+            .or_else(|| png_image(image))
+            .ok_or_else(|| {
+                YaErr::ParseErr(
+                    "Only JPEG and non-interlaced gray or RGB PNG scans are supported".to_string(),
+                )
+            })?;
+        let (width, height) = (image.width as f32, image.height as f32);
+
+        let ann = &self.result.text_ann;
+        let scale = |image_size: f32, page_size: Option<i64>| match page_size {
+            Some(page_size) if page_size > 0 => image_size / page_size as f32,
+            _ => 1.0,
+        };
+        let scale = (scale(width, ann.width), scale(height, ann.height));
+
+        let mut operations = vec![
+            Operation::new("q", vec![]),
+            Operation::new(
+                "cm",
+                vec![
+                    width.into(),
+                    0.into(),
+                    0.into(),
+                    height.into(),
+                    0.into(),
+                    0.into(),
+                ],
+            ),
+            Operation::new("Do", vec!["Im0".into()]),
+            Operation::new("Q", vec![]),
+            Operation::new("BT", vec![]),
+            // neither filled nor stroked
+            Operation::new("Tr", vec![3.into()]),
+        ];
+        for word in ann.words() {
+            operations.extend(word_operations(word, scale, height));
+        }
+        operations.push(Operation::new("ET", vec![]));
+
+        let mut document = Document::with_version("1.5");
+        let font_id = add_text_font(&mut document);
+        let mut image_dict = image.dict;
+        image_dict.set("Type", "XObject");
+        image_dict.set("Subtype", "Image");
+        image_dict.set("Width", image.width);
+        image_dict.set("Height", image.height);
+        let image_id = document.add_object(Stream::new(image_dict, image.data));
+        let content = Content { operations }
+            .encode()
+            .map_err(|e| YaErr::ParseErr(format!("Could not encode the text layer: {}", e)))?;
+        let content_id = document.add_object(Stream::new(dictionary! {}, content));
+
+        let pages_id = document.new_object_id();
+        let page_id = document.add_object(dictionary! {
+            "Type" => "Page",
+            "Parent" => pages_id,
+            "MediaBox" => vec![0.into(), 0.into(), width.into(), height.into()],
+            "Contents" => content_id,
+            "Resources" => dictionary! {
+                "XObject" => dictionary! { "Im0" => image_id },
+                "Font" => dictionary! { "F0" => font_id },
+            },
+        });
+        document.objects.insert(
+            pages_id,
+            Object::Dictionary(dictionary! {
+                "Type" => "Pages",
+                "Kids" => vec![page_id.into()],
+                "Count" => 1,
+            }),
+        );
+        let catalog_id = document.add_object(dictionary! {
+            "Type" => "Catalog",
+            "Pages" => pages_id,
+        });
+        document.trailer.set("Root", catalog_id);
+
+        let mut out = Vec::new();
+        document
+            .save_to(&mut out)
+            .map_err(|e| YaErr::ParseErr(format!("Could not write the PDF: {}", e)))?;
+        Ok(out)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // page of 1000x500 with the words "R&D" and "Пётр"
+    fn response() -> CompletionResponse {
+        let word = |text: &str, l: i64, r: i64| {
+            serde_json::json!({
+                "boundingBox": { "vertices": [
+                    { "x": l.to_string(), "y": "20" },
+                    { "x": l.to_string(), "y": "40" },
+                    { "x": r.to_string(), "y": "40" },
+                    { "x": r.to_string(), "y": "20" }
+                ] },
+                "text": text,
+                "entityIndex": "-1",
+                "textSegments": []
+            })
+        };
+
+        serde_json::from_value(serde_json::json!({
+            "result": {
+                "textAnnotation": {
+                    "width": "1000",
+                    "height": "500",
+                    "blocks": [{
+                        "boundingBox": { "vertices": [] },
+                        "lines": [{
+                            "boundingBox": { "vertices": [] },
+                            "text": "R&D Пётр",
+                            "words": [word("R&D", 10, 90), word("Пётр", 110, 200)],
+                            "textSegments": [],
+                            "orientation": "ANGLE_0"
+                        }],
+                        "languages": [],
+                        "textSegments": [],
+                        "layoutType": "LAYOUT_TYPE_TEXT"
+                    }],
+                    "fullText": "R&D Пётр"
+                }
+            }
+        }))
+        .unwrap()
+    }
+
+    // JPEG headers up to the frame of a 500x250 RGB image
+    fn jpeg() -> Vec<u8> {
+        let mut jpeg = vec![0xFF, 0xD8, 0xFF, 0xE0, 0x00, 0x04, 0x00, 0x00];
+        jpeg.extend([0xFF, 0xC0, 0x00, 0x11, 0x08, 0x00, 0xFA, 0x01, 0xF4, 0x03]);
+        jpeg.extend([0; 9]);
+        jpeg
+    }
+
+    fn png(color_type: u8) -> Vec<u8> {
+        let mut png = b"\x89PNG\r\n\x1a\n".to_vec();
+        let mut chunk = |kind: &[u8], body: &[u8]| {
+            png.extend((body.len() as u32).to_be_bytes());
+            png.extend(kind);
+            png.extend(body);
+            png.extend([0; 4]);
+        };
+        chunk(b"IHDR", &[0, 0, 0, 4, 0, 0, 0, 2, 8, color_type, 0, 0, 0]);
+        chunk(b"IDAT", b"zlib");
+        chunk(b"IEND", b"");
+        png
+    }
+
+    #[test]
+    fn searchable_pdf_lays_invisible_words_over_the_scan() {
+        let pdf = response().to_searchable_pdf(&jpeg()).unwrap();
+        let document = Document::load_mem(&pdf).unwrap();
+
+        let pages = document.get_pages();
+        assert_eq!(pages.len(), 1);
+        let page_id = pages[&1];
+        let page = document.get_dictionary(page_id).unwrap();
+        let media_box = page.get(b"MediaBox").unwrap().as_array().unwrap();
+        assert_eq!(media_box[2].as_float().unwrap(), 500.0);
+
+        let content = Content::decode(&document.get_page_content(page_id).unwrap()).unwrap();
+        let ops = &content.operations;
+        assert!(
+            ops.iter()
+                .any(|op| op.operator == "Tr" && op.operands[0].as_i64().unwrap() == 3)
+        );
+
+        let texts: Vec<String> = ops
+            .iter()
+            .filter(|op| op.operator == "Tj")
+            .map(|op| {
+                let bytes = op.operands[0].as_str().unwrap();
+                let codes: Vec<u16> = bytes
+                    .chunks(2)
+                    .map(|c| u16::from_be_bytes([c[0], c[1]]))
+                    .collect();
+                String::from_utf16(&codes).unwrap()
+            })
+            .collect();
+        assert_eq!(texts, vec!["R&D", "Пётр"]);
+
+        // the word boxes are halved along with the image
+        let tm = ops.iter().find(|op| op.operator == "Tm").unwrap();
+        assert_eq!(tm.operands[4].as_float().unwrap(), 5.0);
+        assert_eq!(tm.operands[5].as_float().unwrap(), 230.0);
+    }
+
+    #[test]
+    fn searchable_pdf_embeds_png_data() {
+        let pdf = response().to_searchable_pdf(&png(2)).unwrap();
+        let document = Document::load_mem(&pdf).unwrap();
+
+        let image = document
+            .objects
+            .values()
+            .filter_map(|o| o.as_stream().ok())
+            .find(|s| s.dict.get(b"Subtype").and_then(Object::as_name).ok() == Some(b"Image"))
+            .unwrap();
+        assert_eq!(image.content, b"zlib");
+        assert_eq!(
+            image.dict.get(b"Filter").unwrap().as_name().unwrap(),
+            b"FlateDecode"
+        );
+
+        assert!(matches!(
+            response().to_searchable_pdf(&png(6)),
+            Err(YaErr::ParseErr(_))
+        ));
+        assert!(response().to_searchable_pdf(b"GIF89a").is_err());
+    }
+}
@@ -1,27 +1,216 @@
-use serde::{Deserialize, Serialize};
+use serde::{Deserialize, Deserializer, Serialize};
+use std::fmt::Display;
+use std::str::FromStr;
 
-#[derive(Clone, Debug, Serialize, Deserialize)]
+// Yandex sends most numbers wrapped into strings (`"x": "120"`), while
+// others come as plain JSON numbers; accept both
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum StrOrNum<T> {
+    Num(T),
+    Str(String),
+}
+
+impl<T> StrOrNum<T>
+where
+    T: FromStr,
+    T::Err: Display,
+{
+    fn into_num<E: serde::de::Error>(self) -> Result<T, E> {
+        match self {
+            StrOrNum::Num(n) => Ok(n),
+            StrOrNum::Str(s) => s.trim().parse().map_err(E::custom),
+        }
+    }
+}
+
+fn de_num<'de, D, T>(deserializer: D) -> Result<T, D::Error>
+where
+    D: Deserializer<'de>,
+    T: FromStr + Deserialize<'de>,
+    T::Err: Display,
+{
+    StrOrNum::<T>::deserialize(deserializer)?.into_num()
+}
+
+fn de_opt_num<'de, D, T>(deserializer: D) -> Result<Option<T>, D::Error>
+where
+    D: Deserializer<'de>,
+    T: FromStr + Deserialize<'de>,
+    T::Err: Display,
+{
+    Option::<StrOrNum<T>>::deserialize(deserializer)?
+        .map(StrOrNum::into_num)
+        .transpose()
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
 pub struct ResultOcr {
     #[serde(rename = "textAnnotation")]
     pub text_ann: Annotation,
     pub page: Option<String>,
 }
 
-#[derive(Clone, Debug, Serialize, Deserialize, Default)]
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize, Default)]
 pub struct Annotation {
-    pub width: Option<String>,
-    pub height: Option<String>,
+    #[serde(default, deserialize_with = "de_opt_num")]
+    pub width: Option<i64>,
+    #[serde(default, deserialize_with = "de_opt_num")]
+    pub height: Option<i64>,
     pub blocks: Option<Vec<Block>>,
     pub entities: Option<Vec<Entity>>,
     pub tables: Option<Vec<Table>>,
     #[serde(rename = "fullText")]
     pub full_text: String,
-    pub rotate: Option<String>,
+    #[serde(default, deserialize_with = "de_opt_num")]
+    pub rotate: Option<i64>,
     pub markdown: Option<String>,
     pub pictures: Option<Vec<Picture>>,
 }
 
-#[derive(Clone, Debug, Serialize, Deserialize, Default)]
+impl Annotation {
+    /// Words of all blocks and lines, in response order
+    pub fn words(&self) -> impl Iterator<Item = &Word> {
+        self.blocks
+            .iter()
+            .flatten()
+            .flat_map(|b| &b.lines)
+            .flat_map(|l| &l.words)
+    }
+
+    /// Text of the segment, taking its offsets as UTF-8 byte offsets into
+    /// `full_text`. `None` when the segment is out of range or does not
+    /// fall on character boundaries, e.g. when counted in characters of a
+    /// non-ASCII text.
+    pub fn segment_text(&self, seg: &TextSegment) -> Option<&str> {
+        let end = seg.start_index.checked_add(seg.length)?;
+        self.full_text.get(seg.start_index..end)
+    }
+
+    /// Words making up the entity at the given index of `entities`
+    pub fn words_for_entity(&self, entity_idx: usize) -> Vec<&Word> {
+        self.words()
+            .filter(|w| w.entity() == Some(entity_idx))
+            .collect()
+    }
+
+    /// Text of the lines like `full_text`, with the words split across lines
+    /// by a hyphen joined back. A line ending in a hyphen right after a
+    /// letter is continued by the next line of the block: when that one
+    /// starts with a lowercase letter the hyphen is taken for a line-break
+    /// artifact and dropped (`recog-` `nition` gives `recognition`),
+    /// otherwise for a real one and kept (`COVID-` `19` gives `COVID-19`).
+    /// Lone dashes and hyphens at the end of a block are left as they are.
+    pub fn dehyphenated_text(&self) -> String {
+        let mut text = String::new();
+        for block in self.blocks.iter().flatten() {
+            let mut lines = block.lines.iter().peekable();
+            while let Some(line) = lines.next() {
+                let line_text = line.text.trim_end();
+                match lines.peek().and_then(|next| hyphen_break(line, next)) {
+                    Some(HyphenBreak::Artifact) => {
+                        text.push_str(line_text.strip_suffix(HYPHENS).unwrap_or(line_text))
+                    }
+                    Some(HyphenBreak::Real) => text.push_str(line_text),
+                    None => {
+                        text.push_str(line_text);
+                        text.push('\n');
+                    }
+                }
+            }
+        }
+
+        text.truncate(text.trim_end_matches('\n').len());
+        text
+    }
+
+    /// Text of the lines ordered top-to-bottom, left-to-right by their
+    /// bounding boxes: blocks sharing a horizontal band are read left to
+    /// right, lines likewise within every block. Lines are separated by a
+    /// newline, blocks by an empty line.
+    pub fn reading_order_text(&self) -> String {
+        let blocks = self.blocks.as_deref().unwrap_or_default();
+
+        reading_order(blocks.iter().map(|b| (&b.bounding_box, b)).collect())
+            .into_iter()
+            .map(|block| {
+                reading_order(block.lines.iter().map(|l| (&l.bounding_box, l)).collect())
+                    .into_iter()
+                    .map(|line| line.text.as_str())
+                    .collect::<Vec<_>>()
+                    .join("\n")
+            })
+            .collect::<Vec<_>>()
+            .join("\n\n")
+    }
+}
+
+// most positions a table grid is rendered with, far beyond any real page
+const MAX_TABLE_CELLS: usize = 1 << 20;
+
+// hyphen-minus, hyphen, soft hyphen and the not sign some OCR engines
+// read line-break hyphens as
+const HYPHENS: [char; 4] = ['-', '\u{2010}', '\u{00AD}', '\u{00AC}'];
+
+// how a line ending in a hyphen continues on the next line
+enum HyphenBreak {
+    // the hyphen only splits the word and is dropped
+    Artifact,
+    // the hyphen is part of the word and is kept
+    Real,
+}
+
+fn hyphen_break(line: &Line, next: &Line) -> Option<HyphenBreak> {
+    let last = match line.words.last() {
+        Some(word) => word.text.trim(),
+        None => line.text.split_whitespace().last()?,
+    };
+    let first = match next.words.first() {
+        Some(word) => word.text.trim(),
+        None => next.text.split_whitespace().next()?,
+    };
+
+    let fragment = last.strip_suffix(HYPHENS)?;
+    if !fragment.chars().last()?.is_alphabetic() {
+        return None;
+    }
+
+    let first = first.chars().next()?;
+    if first.is_lowercase() {
+        Some(HyphenBreak::Artifact)
+    } else if first.is_alphanumeric() {
+        Some(HyphenBreak::Real)
+    } else {
+        None
+    }
+}
+
+// sorts the items into horizontal bands (an item joins a band when its
+// vertical center falls within the band's first item) read top to bottom,
+// each band read left to right
+fn reading_order<T>(mut items: Vec<(&BoundingBox, T)>) -> Vec<T> {
+    items.sort_by_key(|(bbox, _)| bbox.extent().1);
+
+    let mut bands: Vec<(i64, Vec<(&BoundingBox, T)>)> = Vec::new();
+    for (bbox, item) in items {
+        let (_, top, _, bottom) = bbox.extent();
+        let center = (top + bottom) / 2;
+        match bands.last_mut() {
+            Some((band_bottom, band)) if center <= *band_bottom => band.push((bbox, item)),
+            _ => bands.push((bottom, vec![(bbox, item)])),
+        }
+    }
+
+    bands
+        .into_iter()
+        .flat_map(|(_, mut band)| {
+            band.sort_by_key(|(bbox, _)| bbox.extent().0);
+            band.into_iter().map(|(_, item)| item)
+        })
+        .collect()
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize, Default)]
 pub struct Block {
     #[serde(rename = "boundingBox")]
     pub bounding_box: BoundingBox,
@@ -33,18 +222,140 @@ pub struct Block {
     pub layout_type: String,
 }
 
-#[derive(Clone, Debug, Serialize, Deserialize, Default)]
+impl Block {
+    /// Structural role of the block, parsed from `layout_type`
+    pub fn layout(&self) -> LayoutType {
+        LayoutType::from(self.layout_type.as_str())
+    }
+}
+
+/// Structural role of a block as told by its `layoutType`
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub enum LayoutType {
+    Unspecified,
+    Unknown,
+    Text,
+    Header,
+    SectionHeader,
+    Footer,
+    Footnote,
+    Picture,
+    Caption,
+    Title,
+    List,
+    Table,
+    Formula,
+    /// Any value not known to the crate, kept as sent
+    Other(String),
+}
+
+impl LayoutType {
+    /// Value of the `layoutType` field, e.g. `LAYOUT_TYPE_TABLE`
+    pub fn as_str(&self) -> &str {
+        match self {
+            LayoutType::Unspecified => "LAYOUT_TYPE_UNSPECIFIED",
+            LayoutType::Unknown => "LAYOUT_TYPE_UNKNOWN",
+            LayoutType::Text => "LAYOUT_TYPE_TEXT",
+            LayoutType::Header => "LAYOUT_TYPE_HEADER",
+            LayoutType::SectionHeader => "LAYOUT_TYPE_SECTION_HEADER",
+            LayoutType::Footer => "LAYOUT_TYPE_FOOTER",
+            LayoutType::Footnote => "LAYOUT_TYPE_FOOTNOTE",
+            LayoutType::Picture => "LAYOUT_TYPE_PICTURE",
+            LayoutType::Caption => "LAYOUT_TYPE_CAPTION",
+            LayoutType::Title => "LAYOUT_TYPE_TITLE",
+            LayoutType::List => "LAYOUT_TYPE_LIST",
+            LayoutType::Table => "LAYOUT_TYPE_TABLE",
+            LayoutType::Formula => "LAYOUT_TYPE_FORMULA",
+            LayoutType::Other(value) => value,
+        }
+    }
+}
+
+impl From<&str> for LayoutType {
+    fn from(value: &str) -> Self {
+        match value {
+            "LAYOUT_TYPE_UNSPECIFIED" => LayoutType::Unspecified,
+            "LAYOUT_TYPE_UNKNOWN" => LayoutType::Unknown,
+            "LAYOUT_TYPE_TEXT" => LayoutType::Text,
+            "LAYOUT_TYPE_HEADER" => LayoutType::Header,
+            "LAYOUT_TYPE_SECTION_HEADER" => LayoutType::SectionHeader,
+            "LAYOUT_TYPE_FOOTER" => LayoutType::Footer,
+            "LAYOUT_TYPE_FOOTNOTE" => LayoutType::Footnote,
+            "LAYOUT_TYPE_PICTURE" => LayoutType::Picture,
+            "LAYOUT_TYPE_CAPTION" => LayoutType::Caption,
+            "LAYOUT_TYPE_TITLE" => LayoutType::Title,
+            "LAYOUT_TYPE_LIST" => LayoutType::List,
+            "LAYOUT_TYPE_TABLE" => LayoutType::Table,
+            "LAYOUT_TYPE_FORMULA" => LayoutType::Formula,
+            other => LayoutType::Other(other.to_string()),
+        }
+    }
+}
+
+impl Display for LayoutType {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize, Default)]
 pub struct BoundingBox {
     pub vertices: Vec<Vertex>,
 }
 
-#[derive(Clone, Debug, Serialize, Deserialize, Default)]
+impl BoundingBox {
+    // (left, top, right, bottom) of the vertices, zeros when there are none
+    pub(crate) fn extent(&self) -> (i64, i64, i64, i64) {
+        let xs = self.vertices.iter().map(|v| v.x);
+        let ys = self.vertices.iter().map(|v| v.y);
+        (
+            xs.clone().min().unwrap_or_default(),
+            ys.clone().min().unwrap_or_default(),
+            xs.max().unwrap_or_default(),
+            ys.max().unwrap_or_default(),
+        )
+    }
+
+    /// Axis-aligned pixel rectangle enclosing the vertices, empty when there
+    /// are none
+    pub fn rect(&self) -> Rect {
+        let (left, top, right, bottom) = self.extent();
+        Rect {
+            x: left,
+            y: top,
+            width: right - left,
+            height: bottom - top,
+        }
+    }
+
+    /// Whether every vertex lies on a corner of `rect`, i.e. the box is not
+    /// a rotated or skewed quadrilateral
+    pub fn is_axis_aligned(&self) -> bool {
+        let (left, top, right, bottom) = self.extent();
+        self.vertices
+            .iter()
+            .all(|v| (v.x == left || v.x == right) && (v.y == top || v.y == bottom))
+    }
+}
+
+/// Pixel rectangle, `x` and `y` being its top left corner
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Default)]
+pub struct Rect {
+    pub x: i64,
+    pub y: i64,
+    pub width: i64,
+    pub height: i64,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize, Default)]
 pub struct Vertex {
-    pub x: String,
-    pub y: String,
+    #[serde(deserialize_with = "de_num")]
+    pub x: i64,
+    #[serde(deserialize_with = "de_num")]
+    pub y: i64,
 }
 
-#[derive(Clone, Debug, Serialize, Deserialize, Default)]
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize, Default)]
 pub struct Line {
     #[serde(rename = "boundingBox")]
     pub bounding_box: BoundingBox,
@@ -55,7 +366,7 @@ pub struct Line {
     pub orientation: String,
 }
 
-#[derive(Clone, Debug, Serialize, Deserialize, Default)]
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize, Default)]
 pub struct Word {
     #[serde(rename = "boundingBox")]
     pub bounding_box: BoundingBox,
@@ -64,65 +375,485 @@ pub struct Word {
     pub entity_index: String,
     #[serde(rename = "textSegments")]
     pub text_segments: Vec<TextSegment>,
+    /// Recognition confidence in `[0, 1]`, only reported by some models
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub confidence: Option<f64>,
 }
 
-#[derive(Clone, Debug, Serialize, Deserialize, Default)]
+impl Word {
+    /// Pixel rectangle of the word
+    pub fn rect(&self) -> Rect {
+        self.bounding_box.rect()
+    }
+
+    /// Index into `Annotation::entities` of the entity the word belongs to;
+    /// `None` for the "-1" or empty index of words outside any entity
+    pub fn entity(&self) -> Option<usize> {
+        self.entity_index.trim().parse().ok()
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize, Default)]
 pub struct TextSegment {
-    #[serde(rename = "startIndex")]
-    pub start_index: String,
-    pub length: String,
+    // zero values may be left out by Yandex
+    #[serde(rename = "startIndex", default, deserialize_with = "de_num")]
+    pub start_index: usize,
+    #[serde(default, deserialize_with = "de_num")]
+    pub length: usize,
 }
 
-#[derive(Clone, Debug, Serialize, Deserialize, Default)]
+impl TextSegment {
+    /// Offsets of the segment in `Annotation::full_text`, see
+    /// `Annotation::segment_text` to slice it without panicking
+    pub fn range(&self) -> std::ops::Range<usize> {
+        self.start_index..self.start_index.saturating_add(self.length)
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize, Default)]
 pub struct Language {
     #[serde(rename = "languageCode")]
     pub language_code: String,
 }
 
-#[derive(Clone, Debug, Serialize, Deserialize, Default)]
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize, Default)]
 pub struct Entity {
     pub name: String,
     pub text: String,
 }
 
-#[derive(Clone, Debug, Serialize, Deserialize, Default)]
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize, Default)]
 pub struct Table {
     #[serde(rename = "boundingBox")]
     pub bounding_box: BoundingBox,
-    #[serde(rename = "rowCount")]
-    pub row_count: String,
-    #[serde(rename = "columnCount")]
-    pub column_count: String,
+    #[serde(rename = "rowCount", deserialize_with = "de_num")]
+    pub row_count: usize,
+    #[serde(rename = "columnCount", deserialize_with = "de_num")]
+    pub column_count: usize,
     pub cells: Vec<Cell>,
 }
 
-#[derive(Clone, Debug, Serialize, Deserialize, Default)]
+impl Table {
+    // cell texts laid out on a grid; merged cells keep their text in the
+    // top-left position only. The grid is sized by the cells present, within
+    // the declared counts, so that bogus counts or indices sent by the
+    // server can't make it allocate more than `MAX_TABLE_CELLS` positions.
+    fn grid(&self) -> Vec<Vec<&str>> {
+        let extent = |index: fn(&Cell) -> usize, count| {
+            self.cells
+                .iter()
+                .map(|c| index(c).saturating_add(1))
+                .max()
+                .unwrap_or_default()
+                .min(count)
+        };
+        let rows = extent(|c| c.row_index, self.row_count);
+        let columns = extent(|c| c.column_index, self.column_count);
+        if rows.saturating_mul(columns) > MAX_TABLE_CELLS {
+            return Vec::new();
+        }
+
+        let mut grid = vec![vec![""; columns]; rows];
+        for cell in &self.cells {
+            if let Some(pos) = grid
+                .get_mut(cell.row_index)
+                .and_then(|row| row.get_mut(cell.column_index))
+            {
+                *pos = cell.text.as_str();
+            }
+        }
+        grid
+    }
+
+    /// Renders the table as a Markdown table, using the first row as header
+    pub fn to_markdown(&self) -> String {
+        let mut out = String::new();
+        for (i, row) in self.grid().iter().enumerate() {
+            let cells: Vec<String> = row
+                .iter()
+                .map(|t| t.replace('|', "\\|").replace('\n', " "))
+                .collect();
+            out.push_str(&format!("| {} |\n", cells.join(" | ")));
+
+            if i == 0 {
+                out.push_str(&format!("|{}\n", " --- |".repeat(row.len())));
+            }
+        }
+        out
+    }
+
+    /// Renders the table as CSV (RFC 4180 quoting)
+    pub fn to_csv(&self) -> String {
+        let mut out = String::new();
+        for row in self.grid() {
+            let cells: Vec<String> = row
+                .iter()
+                .map(|t| {
+                    if t.contains([',', '"', '\n', '\r']) {
+                        format!("\"{}\"", t.replace('"', "\"\""))
+                    } else {
+                        t.to_string()
+                    }
+                })
+                .collect();
+            out.push_str(&cells.join(","));
+            out.push_str("\r\n");
+        }
+        out
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize, Default)]
 pub struct Cell {
     #[serde(rename = "boundingBox")]
     pub bounding_box: BoundingBox,
-    #[serde(rename = "rowIndex")]
-    pub row_index: String,
-    #[serde(rename = "columnIndex")]
-    pub column_index: String,
-    #[serde(rename = "columnSpan")]
-    pub column_span: String,
-    #[serde(rename = "rowSpan")]
-    pub row_span: String,
+    #[serde(rename = "rowIndex", deserialize_with = "de_num")]
+    pub row_index: usize,
+    #[serde(rename = "columnIndex", deserialize_with = "de_num")]
+    pub column_index: usize,
+    #[serde(rename = "columnSpan", deserialize_with = "de_num")]
+    pub column_span: usize,
+    #[serde(rename = "rowSpan", deserialize_with = "de_num")]
+    pub row_span: usize,
     pub text: String,
     #[serde(rename = "textSegments")]
     pub text_segments: Vec<TextSegment>,
 }
 
-#[derive(Clone, Debug, Serialize, Deserialize, Default)]
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize, Default)]
 pub struct Picture {
     #[serde(rename = "boundingBox")]
     pub bounding_box: BoundingBox,
-    // pub score: String,
+    /// Confidence of the region being a picture, sent by Yandex as a string
+    /// and parsed into `f32`
+    #[serde(
+        default,
+        deserialize_with = "de_opt_num",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub score: Option<f32>,
 }
 
-#[derive(Clone, Debug, Serialize, Deserialize, Default)]
+impl Picture {
+    /// Pixel rectangle of the picture along with its score, e.g. to crop the
+    /// figure out of the scanned page
+    pub fn region(&self) -> PictureRegion {
+        PictureRegion {
+            rect: self.bounding_box.rect(),
+            score: self.score,
+        }
+    }
+}
+
+/// Rectangle of a picture along with the confidence of it being one
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct PictureRegion {
+    pub rect: Rect,
+    pub score: Option<f32>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize, Default)]
 pub struct AsyncRes {
     pub id: String,
     pub description: String,
     pub done: bool,
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn vertex_accepts_string_and_numeric_coordinates() {
+        let v: Vertex = serde_json::from_str(r#"{"x": "12", "y": 34}"#).unwrap();
+        assert_eq!((v.x, v.y), (12, 34));
+
+        assert!(serde_json::from_str::<Vertex>(r#"{"x": "a", "y": "1"}"#).is_err());
+    }
+
+    #[test]
+    fn text_segments_parse_string_and_missing_offsets() {
+        let seg: TextSegment = serde_json::from_str(r#"{"startIndex": "6", "length": 5}"#).unwrap();
+        assert_eq!(seg.range(), 6..11);
+        assert_eq!(&"hello world"[seg.range()], "world");
+
+        let seg: TextSegment = serde_json::from_str(r#"{"length": "5"}"#).unwrap();
+        assert_eq!(seg.range(), 0..5);
+    }
+
+    #[test]
+    fn segment_text_rejects_misaligned_segments() {
+        let annotation = Annotation {
+            full_text: "ID Пётр".to_string(),
+            ..Default::default()
+        };
+        let seg = |start_index, length| TextSegment {
+            start_index,
+            length,
+        };
+
+        assert_eq!(annotation.segment_text(&seg(0, 2)), Some("ID"));
+        assert_eq!(annotation.segment_text(&seg(3, 8)), Some("Пётр"));
+        // inside the two-byte "П"
+        assert_eq!(annotation.segment_text(&seg(4, 2)), None);
+        assert_eq!(annotation.segment_text(&seg(3, 20)), None);
+        assert_eq!(annotation.segment_text(&seg(usize::MAX, 2)), None);
+    }
+
+    #[test]
+    fn picture_regions_parse_the_string_score() {
+        let picture: Picture = serde_json::from_str(
+            r#"{
+                "boundingBox": { "vertices": [
+                    { "x": "10", "y": "20" }, { "x": "10", "y": "120" },
+                    { "x": "60", "y": "120" }, { "x": "60", "y": "20" }
+                ] },
+                "score": "0.875"
+            }"#,
+        )
+        .unwrap();
+
+        assert_eq!(
+            picture.region(),
+            PictureRegion {
+                rect: Rect {
+                    x: 10,
+                    y: 20,
+                    width: 50,
+                    height: 100,
+                },
+                score: Some(0.875),
+            }
+        );
+    }
+
+    fn table() -> Table {
+        let cell = |row_index, column_index, text: &str| Cell {
+            row_index,
+            column_index,
+            column_span: 1,
+            row_span: 1,
+            text: text.to_string(),
+            ..Default::default()
+        };
+
+        Table {
+            row_count: 2,
+            column_count: 2,
+            cells: vec![
+                cell(0, 0, "Item"),
+                cell(0, 1, "Price"),
+                cell(1, 0, "Tea, green"),
+                cell(1, 1, "1|2"),
+            ],
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn table_renders_to_markdown_and_csv() {
+        let t = table();
+        assert_eq!(
+            t.to_markdown(),
+            "| Item | Price |\n| --- | --- |\n| Tea, green | 1\\|2 |\n"
+        );
+        assert_eq!(t.to_csv(), "Item,Price\r\n\"Tea, green\",1|2\r\n");
+    }
+
+    #[test]
+    fn table_grid_ignores_bogus_counts() {
+        let mut t = table();
+        t.row_count = usize::MAX;
+        t.column_count = usize::MAX;
+        assert_eq!(t.to_csv(), "Item,Price\r\n\"Tea, green\",1|2\r\n");
+
+        t.cells[3].row_index = 1 << 30;
+        t.cells[3].column_index = 1 << 30;
+        assert_eq!(t.to_csv(), "");
+    }
+
+    fn bbox(left: i64, top: i64, right: i64, bottom: i64) -> BoundingBox {
+        BoundingBox {
+            vertices: vec![
+                Vertex { x: left, y: top },
+                Vertex { x: left, y: bottom },
+                Vertex {
+                    x: right,
+                    y: bottom,
+                },
+                Vertex { x: right, y: top },
+            ],
+        }
+    }
+
+    fn block(bounding_box: BoundingBox, lines: &[(&str, BoundingBox)]) -> Block {
+        Block {
+            bounding_box,
+            lines: lines
+                .iter()
+                .map(|(text, bounding_box)| Line {
+                    bounding_box: bounding_box.clone(),
+                    text: text.to_string(),
+                    ..Default::default()
+                })
+                .collect(),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn reading_order_text_follows_columns_and_rows() {
+        let annotation = Annotation {
+            blocks: Some(vec![
+                block(
+                    bbox(0, 500, 1000, 550),
+                    &[("footer", bbox(0, 500, 1000, 550))],
+                ),
+                // right column starts slightly higher than the left one
+                block(
+                    bbox(520, 98, 1000, 200),
+                    &[
+                        ("right 2", bbox(520, 150, 1000, 200)),
+                        ("right 1", bbox(520, 98, 1000, 148)),
+                    ],
+                ),
+                block(bbox(0, 100, 480, 150), &[("left", bbox(0, 100, 480, 150))]),
+            ]),
+            ..Default::default()
+        };
+
+        assert_eq!(
+            annotation.reading_order_text(),
+            "left\n\nright 1\nright 2\n\nfooter"
+        );
+    }
+
+    #[test]
+    fn hyphenated_words_are_joined_across_lines() {
+        let lines = |texts: &[&str]| {
+            let lines = texts
+                .iter()
+                .map(|t| (*t, BoundingBox::default()))
+                .collect::<Vec<_>>();
+            block(BoundingBox::default(), &lines)
+        };
+        let mut with_words = lines(&["Распо-", "знавание"]);
+        for line in &mut with_words.lines {
+            line.words = vec![Word {
+                text: line.text.clone(),
+                ..Default::default()
+            }];
+        }
+        let annotation = Annotation {
+            blocks: Some(vec![
+                lines(&["Text recog-", "nition of COVID-", "19 scans -", "a dash"]),
+                lines(&["page 12-", "13 and well-", "Known end-"]),
+                with_words,
+            ]),
+            ..Default::default()
+        };
+
+        assert_eq!(
+            annotation.dehyphenated_text(),
+            "Text recognition of COVID-19 scans -\na dash\npage 12-\n13 and well-Known end-\nРаспознавание"
+        );
+    }
+
+    #[test]
+    fn words_are_flattened_with_their_rects() {
+        let word = |text: &str, bounding_box| Word {
+            bounding_box,
+            text: text.to_string(),
+            ..Default::default()
+        };
+        let mut first = block(bbox(0, 0, 100, 50), &[("a b", bbox(0, 0, 100, 50))]);
+        first.lines[0].words = vec![
+            word("a", bbox(0, 0, 40, 50)),
+            word("b", bbox(60, 10, 100, 50)),
+        ];
+        let mut second = block(bbox(0, 60, 100, 90), &[("c", bbox(0, 60, 100, 90))]);
+        second.lines[0].words = vec![word("c", bbox(0, 60, 100, 90))];
+        let annotation = Annotation {
+            blocks: Some(vec![first, second]),
+            ..Default::default()
+        };
+
+        let words = annotation.words().collect::<Vec<_>>();
+        assert_eq!(
+            words.iter().map(|w| w.text.as_str()).collect::<Vec<_>>(),
+            vec!["a", "b", "c"]
+        );
+        assert_eq!(
+            words[1].rect(),
+            Rect {
+                x: 60,
+                y: 10,
+                width: 40,
+                height: 40
+            }
+        );
+    }
+
+    #[test]
+    fn bounding_box_rect_encloses_rotated_boxes() {
+        let bbox = |points: [(i64, i64); 4]| BoundingBox {
+            vertices: points.map(|(x, y)| Vertex { x, y }).to_vec(),
+        };
+
+        let straight = bbox([(10, 20), (50, 20), (50, 30), (10, 30)]);
+        assert!(straight.is_axis_aligned());
+
+        let rotated = bbox([(12, 20), (50, 24), (48, 34), (10, 30)]);
+        assert!(!rotated.is_axis_aligned());
+        assert_eq!(
+            rotated.rect(),
+            Rect {
+                x: 10,
+                y: 20,
+                width: 40,
+                height: 14
+            }
+        );
+        assert_eq!(BoundingBox::default().rect(), Rect::default());
+    }
+
+    #[test]
+    fn words_are_linked_to_their_entities() {
+        let word = |text: &str, entity_index: &str| Word {
+            text: text.to_string(),
+            entity_index: entity_index.to_string(),
+            ..Default::default()
+        };
+        let mut first = block(bbox(0, 0, 100, 50), &[("Ivan Petrov", bbox(0, 0, 100, 50))]);
+        first.lines[0].words = vec![word("Ivan", "0"), word("Petrov", "0"), word("born", "-1")];
+        let mut second = block(
+            bbox(0, 60, 100, 90),
+            &[("01.01.1990", bbox(0, 60, 100, 90))],
+        );
+        second.lines[0].words = vec![word("01.01.1990", "1"), word("", "")];
+        let annotation = Annotation {
+            blocks: Some(vec![first, second]),
+            ..Default::default()
+        };
+
+        let texts = |idx| {
+            annotation
+                .words_for_entity(idx)
+                .iter()
+                .map(|w| w.text.as_str())
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(texts(0), vec!["Ivan", "Petrov"]);
+        assert_eq!(texts(1), vec!["01.01.1990"]);
+        assert!(texts(2).is_empty());
+    }
+
+    #[test]
+    fn annotation_optional_dimensions() {
+        let a: Annotation =
+            serde_json::from_str(r#"{"width": "1240", "fullText": "", "rotate": 90}"#).unwrap();
+        assert_eq!(a.width, Some(1240));
+        assert_eq!(a.height, None);
+        assert_eq!(a.rotate, Some(90));
+    }
+}